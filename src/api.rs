@@ -0,0 +1,780 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::Shutdown;
+use std::os::fd::AsFd;
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+
+use crate::prelude::*;
+
+/// The prefix of every libpod API path.
+const PREFIX: &str = "/v4.0.0/libpod";
+
+/// How long to wait for an exec session to be marked as finished once its output has closed.
+const EXEC_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How Box talks to Podman.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    /// Shell out to the `podman` command line.
+    Cli,
+    /// Use the libpod REST API over Podman's unix socket.
+    Api,
+}
+
+impl Backend {
+    /// Determine the backend to use from `$BOX_BACKEND`.
+    pub fn from_env() -> Result<Self> {
+        match std::env::var("BOX_BACKEND").as_deref() {
+            Err(_) | Ok("") | Ok("cli") => Ok(Self::Cli),
+            Ok("api") => Ok(Self::Api),
+            Ok(other) => {
+                let err = eyre!("Unknown backend {other}")
+                    .note("BOX_BACKEND is set to an unrecognized value.")
+                    .suggestion("Supported backends are 'cli' and 'api'.");
+
+                Err(err)
+            }
+        }
+    }
+}
+
+/// A response from the API.
+#[derive(Debug)]
+struct Response {
+    status : u16,
+    body   : Vec<u8>,
+}
+
+/// A minimal client for the libpod REST API.
+pub struct Client {
+    socket: PathBuf,
+}
+
+impl Client {
+    /// Connect to the API if it's the selected backend, returning `None` if the CLI should be used instead.
+    ///
+    /// The socket is `$BOX_PODMAN_SOCKET` if set, or the rootless socket under `$XDG_RUNTIME_DIR` otherwise.
+    pub fn connect() -> Result<Option<Self>> {
+        if Backend::from_env()? == Backend::Cli {
+            return Ok(None)
+        }
+
+        let socket = match std::env::var_os("BOX_PODMAN_SOCKET") {
+            Some(path) => PathBuf::from(path),
+            None => {
+                let runtime = std::env::var_os("XDG_RUNTIME_DIR")
+                    .context("Could not determine the location of the Podman socket")
+                    .suggestion("Set BOX_PODMAN_SOCKET to the path of the socket.")?;
+
+                PathBuf::from(runtime).join("podman/podman.sock")
+            }
+        };
+
+        if !socket.exists() {
+            let err = eyre!("Podman socket {} does not exist", socket.to_string_lossy())
+                .suggestion("Is the Podman API service running? Try 'systemctl --user enable --now podman.socket'.")
+                .suggestion("Alternatively, unset BOX_BACKEND to use the Podman command line instead.");
+
+            return Err(err)
+        }
+
+        Ok(Some(Self { socket }))
+    }
+
+    fn stream(&self) -> Result<UnixStream> {
+        UnixStream::connect(&self.socket)
+            .with_context(|| format!("Fault when connecting to Podman socket {}", self.socket.to_string_lossy()))
+    }
+
+    /// Write a request to the provided stream.
+    fn send(stream: &mut UnixStream, method: &str, path: &str, body: Option<&Value>, upgrade: bool) -> Result<()> {
+        debug!("API request: {method} {path} {body:?}");
+
+        let body = body
+            .map(Value::to_string)
+            .unwrap_or_default();
+
+        let connection = match upgrade {
+            true  => "Connection: Upgrade\r\nUpgrade: tcp\r\n",
+            false => "Connection: close\r\n",
+        };
+
+        let head = format!(
+            "{method} {PREFIX}{path} HTTP/1.1\r\nHost: podman\r\nContent-Type: application/json\r\nContent-Length: {}\r\n{connection}\r\n",
+            body.len()
+        );
+
+        stream
+            .write_all(head.as_bytes())
+            .and_then(|_| stream.write_all(body.as_bytes()))
+            .context("Fault when writing to Podman socket")
+    }
+
+    /// Make a request, returning the response no matter its status.
+    fn request(&self, method: &str, path: &str, body: Option<&Value>) -> Result<Response> {
+        let mut stream = self.stream()?;
+
+        Self::send(&mut stream, method, path, body, false)?;
+
+        let mut reader = BufReader::new(stream);
+
+        let (status, headers) = read_head(&mut reader)?;
+        let body = read_body(&mut reader, &headers)?;
+
+        debug!("API response: {status} ({} bytes)", body.len());
+
+        Ok(Response { status, body })
+    }
+
+    /// Make a request, returning an error if it failed.
+    fn checked(&self, method: &str, path: &str, body: Option<&Value>) -> Result<Response> {
+        let response = self.request(method, path, body)?;
+
+        check(method, path, response)
+    }
+
+    /// Make a GET request, deserializing the response.
+    pub fn get<T: DeserializeOwned>(&self, path: &str) -> Result<T> {
+        let response = self.checked("GET", path, None)?;
+
+        serde_json::from_slice(&response.body)
+            .context("Failed to deserialize API response")
+    }
+
+    /// Make a POST request, ignoring the response.
+    pub fn post(&self, path: &str, body: Option<&Value>) -> Result<()> {
+        self
+            .checked("POST", path, body)
+            .map(drop)
+    }
+
+    /// Make a DELETE request, ignoring the response.
+    pub fn delete(&self, path: &str) -> Result<()> {
+        self
+            .checked("DELETE", path, None)
+            .map(drop)
+    }
+
+    /// Query a listing endpoint, returning the ID of every entry.
+    pub fn list(&self, path: &str) -> Result<Vec<String>> {
+        #[derive(Debug, Deserialize)]
+        struct Entry {
+            #[serde(rename = "Id")]
+            id: String
+        }
+
+        let entries: Vec<Entry> = self.get(path)?;
+
+        Ok(
+            entries
+                .into_iter()
+                .map(|e| e.id)
+                .collect()
+        )
+    }
+
+    /// Query an `exists` endpoint.
+    pub fn exists(&self, path: &str) -> Result<bool> {
+        let response = self.request("GET", path, None)?;
+
+        match response.status {
+            204 => Ok(true),
+            404 => Ok(false),
+            _ => check("GET", path, response).map(|_| false)
+        }
+    }
+
+    /// Create a container from a spec generator, returning its ID.
+    pub fn create(&self, spec: &Value) -> Result<String> {
+        #[derive(Debug, Deserialize)]
+        struct Created {
+            #[serde(rename = "Id")]
+            id: String
+        }
+
+        let response = self.checked("POST", "/containers/create", Some(spec))?;
+
+        let Created { id } = serde_json::from_slice(&response.body)
+            .context("Failed to deserialize API response")?;
+
+        Ok(id)
+    }
+
+    /// Execute a command inside a container without a TTY, forwarding standard input and output.
+    /// Returns the exit code of the command.
+    pub fn exec(&self, ctr: &str, cmd: &[String], env: &[String], workdir: Option<&str>) -> Result<i32> {
+        #[derive(Debug, Deserialize)]
+        struct Created {
+            #[serde(rename = "Id")]
+            id: String
+        }
+
+        #[derive(Debug, Deserialize)]
+        struct Inspect {
+            #[serde(rename = "Running")]
+            running   : bool,
+            #[serde(rename = "ExitCode")]
+            exit_code : i32,
+        }
+
+        let spec = json!({
+            "AttachStdin"  : true,
+            "AttachStdout" : true,
+            "AttachStderr" : true,
+            "Tty"          : false,
+            "Cmd"          : cmd,
+            "Env"          : env,
+            "WorkingDir"   : workdir,
+        });
+
+        let response = self.checked(
+            "POST",
+            &format!("/containers/{}/exec", encode(ctr)),
+            Some(&spec)
+        )?;
+
+        let Created { id } = serde_json::from_slice(&response.body)
+            .context("Failed to deserialize API response")?;
+
+        let mut stream = self.stream()?;
+
+        Self::send(
+            &mut stream,
+            "POST",
+            &format!("/exec/{id}/start"),
+            Some(&json!({ "Detach": false, "Tty": false })),
+            true
+        )?;
+
+        let mut reader = BufReader::new(
+            stream
+                .try_clone()
+                .context("Fault when cloning Podman socket")?
+        );
+
+        let (status, _) = read_head(&mut reader)?;
+
+        if status >= 400 {
+            bail!("Failed to start exec session (status {status})")
+        }
+
+        // Standard input is copied on its own thread. A blocking read can't be interrupted, so if the
+        // command exits without reading all of it, the thread is left waiting until Box itself exits.
+        // It reads from a duplicate of the descriptor rather than through the standard library's handle,
+        // so it never holds the lock on that handle while it waits.
+        let mut input = std::io::stdin()
+            .as_fd()
+            .try_clone_to_owned()
+            .map(File::from)
+            .context("Fault when duplicating standard input")?;
+
+        std::thread::spawn(move || {
+            let _ = std::io::copy(&mut input, &mut stream);
+            let _ = stream.shutdown(Shutdown::Write);
+        });
+
+        demux(
+            &mut reader,
+            &mut std::io::stdout(),
+            &mut std::io::stderr()
+        )?;
+
+        // The stream can close before the session is marked as finished, so back off
+        // between checks rather than giving up straight away.
+        let started = Instant::now();
+        let mut delay = Duration::from_millis(10);
+
+        loop {
+            let session: Inspect = self.get(
+                &format!("/exec/{id}/json")
+            )?;
+
+            if !session.running {
+                return Ok(session.exit_code)
+            }
+
+            if started.elapsed() > EXEC_TIMEOUT {
+                bail!("Exec session {id} was still running {} seconds after its output closed", EXEC_TIMEOUT.as_secs())
+            }
+
+            std::thread::sleep(delay);
+
+            delay = (delay * 2).min(Duration::from_secs(1));
+        }
+    }
+}
+
+/// Read the status line and headers of a response, returning the status code and (lowercased) headers.
+fn read_head(reader: &mut impl BufRead) -> Result<(u16, Vec<(String, String)>)> {
+    let mut line = String::new();
+
+    reader
+        .read_line(&mut line)
+        .context("Fault when reading from Podman socket")?;
+
+    let status = line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|s| s.parse().ok())
+        .with_context(|| format!("Malformed status line {line:?}"))?;
+
+    let mut headers = vec![];
+
+    loop {
+        line.clear();
+
+        reader
+            .read_line(&mut line)
+            .context("Fault when reading from Podman socket")?;
+
+        let line = line.trim_end();
+
+        if line.is_empty() {
+            break
+        }
+
+        if let Some((key, value)) = line.split_once(':') {
+            headers.push((
+                key.trim().to_lowercase(),
+                value.trim().to_owned()
+            ))
+        }
+    }
+
+    Ok((status, headers))
+}
+
+/// Read the body of a response with the provided headers, in whichever framing it uses.
+fn read_body(reader: &mut impl BufRead, headers: &[(String, String)]) -> Result<Vec<u8>> {
+    let header = |name: &str| headers
+        .iter()
+        .find(|(k, _)| k == name)
+        .map(|(_, v)| v.as_str());
+
+    let mut body = vec![];
+
+    if header("transfer-encoding").is_some_and(|v| v.eq_ignore_ascii_case("chunked")) {
+        let mut line = String::new();
+
+        loop {
+            line.clear();
+
+            let read = reader
+                .read_line(&mut line)
+                .context("Fault when reading from Podman socket")?;
+
+            if read == 0 {
+                bail!("Chunked response ended unexpectedly")
+            }
+
+            // Chunk extensions (after a semicolon) carry nothing Box needs.
+            let size = line
+                .split(';')
+                .next()
+                .unwrap_or_default()
+                .trim();
+
+            let size = usize::from_str_radix(size, 16)
+                .with_context(|| format!("Malformed chunk size {line:?}"))?;
+
+            if size == 0 {
+                break
+            }
+
+            let start = body.len();
+
+            body.resize(start + size, 0);
+
+            let mut terminator = [0; 2];
+
+            reader
+                .read_exact(&mut body[start..])
+                .and_then(|_| reader.read_exact(&mut terminator))
+                .context("Fault when reading from Podman socket")?;
+
+            if &terminator != b"\r\n" {
+                bail!("Malformed chunk terminator {terminator:?}")
+            }
+        }
+
+        // Any trailers are skipped, up to the blank line that ends the response.
+        loop {
+            line.clear();
+
+            let read = reader
+                .read_line(&mut line)
+                .context("Fault when reading from Podman socket")?;
+
+            if read == 0 || line.trim_end().is_empty() {
+                break
+            }
+        }
+    }
+    else if let Some(length) = header("content-length").and_then(|v| v.parse().ok()) {
+        body.resize(length, 0);
+
+        reader
+            .read_exact(&mut body)
+            .context("Fault when reading from Podman socket")?;
+    }
+    else {
+        reader
+            .read_to_end(&mut body)
+            .context("Fault when reading from Podman socket")?;
+    }
+
+    Ok(body)
+}
+
+/// Turn an error response into a well-formed error, passing successful ones through.
+fn check(method: &str, path: &str, response: Response) -> Result<Response> {
+    if response.status < 400 {
+        return Ok(response)
+    }
+
+    // Podman describes errors with a JSON object, but fall back to the raw body just in case.
+    let message = serde_json::from_slice::<Value>(&response.body)
+        .ok()
+        .and_then(|v| v["message"].as_str().map(str::to_owned))
+        .unwrap_or_else(|| String::from_utf8_lossy(&response.body).into_owned());
+
+    let err = eyre!("API request failed")
+        .section(format!("{method} {path}").header("Request:"))
+        .section(format!("{} {message}", response.status).header("Response:"))
+        .note("This is likely due to invalid input or a bug in Box.");
+
+    Err(err)
+}
+
+/// Copy the multiplexed output of an exec session to `stdout` and `stderr` until the stream closes.
+/// 
+/// Each frame has an 8-byte header: the stream (1 for standard output, 2 for standard error),
+/// 3 bytes of padding and the length of the frame as a big-endian 32-bit integer.
+fn demux(reader: &mut impl Read, stdout: &mut impl Write, stderr: &mut impl Write) -> Result<()> {
+    let mut header = [0; 8];
+
+    loop {
+        match reader.read_exact(&mut header) {
+            Ok(()) => (),
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e).context("Fault when reading from Podman socket")
+        }
+
+        let length = u32::from_be_bytes([header[4], header[5], header[6], header[7]]);
+        let mut frame = vec![0; length as usize];
+
+        reader
+            .read_exact(&mut frame)
+            .context("Fault when reading from Podman socket")?;
+
+        let out: &mut dyn Write = match header[0] {
+            2 => stderr,
+            _ => stdout,
+        };
+
+        out
+            .write_all(&frame)
+            .and_then(|_| out.flush())
+            .context("Fault when writing command output")?;
+    }
+}
+
+/// Percent-encode a string for use in a path segment or query parameter.
+pub fn encode(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+
+    for byte in s.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => out.push(byte as char),
+            _ => out += &format!("%{byte:02X}")
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::net::UnixListener;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread::JoinHandle;
+
+    use super::*;
+
+    /// Bind a fake Podman socket that answers each connection with the next canned response,
+    /// returning a client for it and a handle that yields the requests it received.
+    fn serve(responses: Vec<Vec<u8>>) -> (Client, JoinHandle<Vec<String>>) {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+        let socket = std::env::temp_dir().join(format!(
+            "box-api-{}-{}.sock",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+
+        let _ = std::fs::remove_file(&socket);
+
+        let listener = UnixListener::bind(&socket).unwrap();
+        let path     = socket.clone();
+
+        let handle = std::thread::spawn(move || {
+            let mut requests = vec![];
+
+            for response in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+                let mut request = String::new();
+                reader.read_line(&mut request).unwrap();
+
+                let mut length = 0;
+
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+
+                    if line.trim_end().is_empty() {
+                        break
+                    }
+
+                    if let Some(v) = line.to_lowercase().strip_prefix("content-length:") {
+                        length = v.trim().parse().unwrap();
+                    }
+                }
+
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+
+                let mut parts = request.split_whitespace();
+
+                requests.push(format!(
+                    "{} {} {}",
+                    parts.next().unwrap(),
+                    parts.next().unwrap(),
+                    String::from_utf8(body).unwrap()
+                ).trim_end().to_owned());
+
+                stream.write_all(&response).unwrap();
+            }
+
+            let _ = std::fs::remove_file(&path);
+
+            requests
+        });
+
+        (Client { socket }, handle)
+    }
+
+    fn response(status: &str, body: &str) -> Vec<u8> {
+        format!("HTTP/1.1 {status}\r\nContent-Length: {}\r\n\r\n{body}", body.len()).into_bytes()
+    }
+
+    fn chunked(status: &str, chunks: &[&str]) -> Vec<u8> {
+        let mut out = format!("HTTP/1.1 {status}\r\nTransfer-Encoding: chunked\r\n\r\n");
+
+        for chunk in chunks {
+            out += &format!("{:x}\r\n{chunk}\r\n", chunk.len());
+        }
+
+        out += "0\r\n\r\n";
+        out.into_bytes()
+    }
+
+    fn frame(stream: u8, data: &[u8]) -> Vec<u8> {
+        let mut out = vec![stream, 0, 0, 0];
+
+        out.extend((data.len() as u32).to_be_bytes());
+        out.extend(data);
+        out
+    }
+
+    #[test]
+    fn read_head_parses_status_and_headers() {
+        let mut data = &b"HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nX-Thing:  a:b \r\n\r\nrest"[..];
+
+        let (status, headers) = read_head(&mut data).unwrap();
+
+        assert_eq!(status, 200);
+        assert_eq!(headers, [
+            ("content-type".to_owned(), "application/json".to_owned()),
+            ("x-thing".to_owned(), "a:b".to_owned()),
+        ]);
+        assert_eq!(data, b"rest");
+    }
+
+    #[test]
+    fn read_head_rejects_malformed_status() {
+        let mut data = &b"garbage\r\n\r\n"[..];
+
+        assert!(read_head(&mut data).is_err());
+    }
+
+    #[test]
+    fn read_body_respects_content_length() {
+        let headers = [("content-length".to_owned(), "5".to_owned())];
+        let mut data = &b"hello world"[..];
+
+        assert_eq!(read_body(&mut data, &headers).unwrap(), b"hello");
+        assert_eq!(data, b" world");
+    }
+
+    #[test]
+    fn read_body_reads_to_end_without_framing() {
+        let mut data = &b"hello world"[..];
+
+        assert_eq!(read_body(&mut data, &[]).unwrap(), b"hello world");
+    }
+
+    #[test]
+    fn read_body_decodes_chunks() {
+        let headers = [("transfer-encoding".to_owned(), "Chunked".to_owned())];
+        let mut data = &b"4;ext=1\r\nWiki\r\n5\r\npedia\r\n0\r\nExpires: never\r\n\r\nnext"[..];
+
+        assert_eq!(read_body(&mut data, &headers).unwrap(), b"Wikipedia");
+        // Trailers are consumed, but nothing after them.
+        assert_eq!(data, b"next");
+    }
+
+    #[test]
+    fn read_body_rejects_bad_chunks() {
+        let headers = [("transfer-encoding".to_owned(), "chunked".to_owned())];
+
+        for data in [&b"4\r\nWikiXX0\r\n\r\n"[..], b"zz\r\n", b"4\r\nWiki\r\n"] {
+            let mut data = data;
+            assert!(read_body(&mut data, &headers).is_err());
+        }
+    }
+
+    #[test]
+    fn check_reports_error_message() {
+        let _ = color_eyre::install();
+
+        let ok = Response { status: 200, body: b"{}".to_vec() };
+        assert!(check("GET", "/ok", ok).is_ok());
+
+        let failed = Response {
+            status : 500,
+            body   : br#"{"cause":"boom","message":"something broke","response":500}"#.to_vec()
+        };
+
+        let err = format!("{:?}", check("GET", "/fail", failed).unwrap_err());
+
+        assert!(err.contains("500 something broke"), "{err}");
+        assert!(err.contains("GET /fail"), "{err}");
+
+        let raw = Response { status: 404, body: b"not json".to_vec() };
+        let err = format!("{:?}", check("GET", "/raw", raw).unwrap_err());
+
+        assert!(err.contains("404 not json"), "{err}");
+    }
+
+    #[test]
+    fn exists_maps_statuses() {
+        let (client, server) = serve(vec![
+            response("204 No Content", ""),
+            response("404 Not Found", r#"{"message":"no such container"}"#),
+            response("500 Internal Server Error", r#"{"message":"boom"}"#),
+        ]);
+
+        assert!(client.exists("/containers/a/exists").unwrap());
+        assert!(!client.exists("/containers/b/exists").unwrap());
+        assert!(client.exists("/containers/c/exists").is_err());
+
+        assert_eq!(server.join().unwrap(), [
+            "GET /v4.0.0/libpod/containers/a/exists",
+            "GET /v4.0.0/libpod/containers/b/exists",
+            "GET /v4.0.0/libpod/containers/c/exists",
+        ]);
+    }
+
+    #[test]
+    fn list_collects_ids() {
+        let (client, server) = serve(vec![
+            chunked("200 OK", &[r#"[{"Id":"aaa","Names":["x"]},"#, r#"{"Id":"bbb"}]"#]),
+        ]);
+
+        assert_eq!(client.list("/containers/json?all=true").unwrap(), ["aaa", "bbb"]);
+        assert_eq!(server.join().unwrap(), ["GET /v4.0.0/libpod/containers/json?all=true"]);
+    }
+
+    #[test]
+    fn create_returns_id() {
+        let (client, server) = serve(vec![
+            response("201 Created", r#"{"Id":"new","Warnings":[]}"#),
+        ]);
+
+        let spec = json!({ "name": "dev" });
+
+        assert_eq!(client.create(&spec).unwrap(), "new");
+        assert_eq!(server.join().unwrap(), [r#"POST /v4.0.0/libpod/containers/create {"name":"dev"}"#]);
+    }
+
+    #[test]
+    fn exec_waits_for_exit_code() {
+        let mut start = b"HTTP/1.1 101 UPGRADED\r\nConnection: Upgrade\r\nUpgrade: tcp\r\n\r\n".to_vec();
+
+        // An empty frame, as the output would go to the test harness' own standard output.
+        start.extend(frame(1, b""));
+
+        let (client, server) = serve(vec![
+            response("201 Created", r#"{"Id":"ex1"}"#),
+            start,
+            // Still marked as running once the output has closed.
+            response("200 OK", r#"{"Running":true,"ExitCode":0}"#),
+            response("200 OK", r#"{"Running":false,"ExitCode":3}"#),
+        ]);
+
+        let code = client
+            .exec("dev", &["true".to_owned()], &["A=b".to_owned()], Some("/src"))
+            .unwrap();
+
+        assert_eq!(code, 3);
+
+        let requests = server.join().unwrap();
+
+        assert!(requests[0].starts_with("POST /v4.0.0/libpod/containers/dev/exec "));
+        assert!(requests[0].contains(r#""Cmd":["true"]"#));
+        assert!(requests[0].contains(r#""Env":["A=b"]"#));
+        assert!(requests[0].contains(r#""WorkingDir":"/src""#));
+        assert_eq!(requests[1], r#"POST /v4.0.0/libpod/exec/ex1/start {"Detach":false,"Tty":false}"#);
+        assert_eq!(requests[2..], ["GET /v4.0.0/libpod/exec/ex1/json", "GET /v4.0.0/libpod/exec/ex1/json"]);
+    }
+
+    #[test]
+    fn demux_splits_streams() {
+        let mut data = frame(1, b"hello ");
+
+        data.extend(frame(2, b"oops"));
+        data.extend(frame(1, b"world"));
+        data.extend(frame(1, b""));
+
+        let mut stdout = vec![];
+        let mut stderr = vec![];
+
+        demux(&mut &data[..], &mut stdout, &mut stderr).unwrap();
+
+        assert_eq!(stdout, b"hello world");
+        assert_eq!(stderr, b"oops");
+    }
+
+    #[test]
+    fn demux_rejects_truncated_frames() {
+        let data = &frame(1, b"hello")[..9];
+
+        assert!(demux(&mut &data[..], &mut vec![], &mut vec![]).is_err());
+    }
+
+    #[test]
+    fn encode_escapes_reserved_characters() {
+        assert_eq!(encode("box-dev_1.0~"), "box-dev_1.0~");
+        assert_eq!(encode("a/b c"), "a%2Fb%20c");
+        assert_eq!(encode(r#"{"dangling":["false"]}"#), "%7B%22dangling%22%3A%5B%22false%22%5D%7D");
+        assert_eq!(encode("é"), "%C3%A9");
+    }
+}
@@ -1,11 +1,15 @@
-use std::collections::{HashMap, HashSet};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::ffi::OsString;
 use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::time::Duration;
 
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 use crate::prelude::*;
 use crate::podman::*;
+use crate::template::*;
 use crate::CommandExt;
 
 pub type Definitions = Vec<Definition>;
@@ -17,12 +21,17 @@ pub struct Definition {
     pub path: PathBuf,
     /// The first line of the definition.
     pub bang: String,
-    /// The [`seahash`] of the definition.
+    /// The [`seahash`] of the definition, as determined by its [`HashMode`].
     pub hash: u64,
+    /// The [`seahash`] of the definition's unmodified text.
+    pub raw: u64,
     /// The combined hash of the definition and all of its dependencies.
     /// 
     /// Not computed by constructors; defaults to the same value as `hash`.
     pub tree: u64,
+    /// The [`seahash`] of what the definition resolves to beyond its text - the values of any
+    /// allowed variables (if hermetic) and its pinned base images. Already folded into `hash`.
+    pub inputs: u64,
     /// Deserialized TOML metadata.
     pub meta: Metadata,
 }
@@ -33,13 +42,361 @@ pub struct Metadata {
     /// The name of any definitions this one depends on, if any.
     #[serde(default)]
     pub depends_on    : Vec<String>,
+    /// How the definition should be hashed; defaults to `$BOX_HASH_MODE` (or raw, if unset.)
+    #[serde(default)]
+    pub hash          : Option<HashMode>,
+    /// Whether or not the definition should be built with a minimal environment.
+    #[serde(default)]
+    pub hermetic      : bool,
+    /// Additional environment variables to allow in hermetic builds.
+    #[serde(default)]
+    pub env           : Vec<String>,
+}
+
+impl Metadata {
+    /// Parse the TOML metadata out of the text of a definition of the provided kind.
+    pub fn parse(data: &str, kind: Kind) -> Result<Self> {
+        let prefix = kind.metadata_prefix();
+
+        let meta = data
+            .lines()
+            .filter(|l| l.starts_with(prefix))
+            .fold(String::new(), |mut acc, line| {
+                acc += line.trim_start_matches(prefix).trim();
+                acc += "\n";
+                acc
+            });
+
+        toml::from_str(&meta)
+            .context("Failed to deserialize TOML frontmatter")
+            .suggestion("Did you make a typo?")
+    }
+}
+
+/// Environment variables that hermetic builds always have access to, as Box, Podman and Buildah need them to function.
+pub const HERMETIC_DEFAULTS: [&str; 15] = [
+    "PATH",
+    "HOME",
+    "USER",
+    "LOGNAME",
+    "TERM",
+    "LANG",
+    "TMPDIR",
+    "XDG_RUNTIME_DIR",
+    "XDG_CONFIG_HOME",
+    "XDG_DATA_HOME",
+    "REGISTRY_AUTH_FILE",
+    "BOX_DEFINITION_DIR",
+    "BOX_BACKEND",
+    "BOX_PODMAN_SOCKET",
+    "RUST_LOG",
+];
+
+/// Prefixes of environment variables that hermetic builds always have access to.
+pub const HERMETIC_PREFIXES: [&str; 3] = [
+    "__BOX_BUILD_",
+    "CONTAINERS_",
+    "BUILDAH_",
+];
+
+/// How a definition's text is hashed to detect changes.
+#[derive(Debug, Default, Clone, Copy, Hash, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HashMode {
+    /// Hash the definition byte-for-byte.
+    #[default]
+    Raw,
+    /// Ignore comments (excluding metadata), trailing whitespace and blank lines.
+    Normalized,
+}
+
+impl HashMode {
+    /// Determine the default hash mode from `$BOX_HASH_MODE`.
+    pub fn from_env() -> Result<Self> {
+        match std::env::var("BOX_HASH_MODE").as_deref() {
+            Err(_) | Ok("") | Ok("raw") => Ok(Self::Raw),
+            Ok("normalized") => Ok(Self::Normalized),
+            Ok(other) => {
+                let err = eyre!("Unknown hash mode {other}")
+                    .note("BOX_HASH_MODE is set to an unrecognized value.")
+                    .suggestion("Supported modes are 'raw' and 'normalized'.");
+
+                Err(err)
+            }
+        }
+    }
+
+    /// Get the text that should be hashed for a definition of the provided kind.
+    pub fn apply<'a>(self, script: &'a str, kind: Kind) -> Cow<'a, str> {
+        match self {
+            Self::Raw => Cow::Borrowed(script),
+            Self::Normalized => Cow::Owned(
+                normalize(script, kind)
+            )
+        }
+    }
+}
+
+/// Normalize the text of a definition, removing full-line comments, trailing whitespace and blank lines.
+/// 
+/// The shebang and metadata lines are always kept, as are the bodies of heredocs (outside of Rhai definitions.)
+pub fn normalize(script: &str, kind: Kind) -> String {
+    let comment = match kind {
+        Kind::Rhai => "//",
+        _ => "#"
+    };
+
+    let metadata = kind.metadata_prefix();
+
+    let mut out     = String::with_capacity(script.len());
+    let mut pending = VecDeque::new();
+
+    for (idx, line) in script.lines().enumerate() {
+        // Heredoc bodies end up in the image, so they're kept byte-for-byte.
+        if let Some((delimiter, tabs)) = pending.front() {
+            let terminator = match tabs {
+                true  => line.trim_start_matches('\t'),
+                false => line
+            };
+
+            if terminator == delimiter {
+                pending.pop_front();
+            }
+
+            out += line;
+            out += "\n";
+            continue
+        }
+
+        let line    = line.trim_end();
+        let trimmed = line.trim_start();
+
+        if idx != 0 && (trimmed.is_empty() || (trimmed.starts_with(comment) && !trimmed.starts_with(metadata))) {
+            continue
+        }
+
+        if kind != Kind::Rhai {
+            pending.extend(heredocs(line));
+        }
+
+        out += line;
+        out += "\n";
+    }
+
+    out
+}
+
+/// Find the heredocs started on a line, returning their delimiters in order (and whether their
+/// terminators may be indented with tabs, as with `<<-`.)
+/// 
+/// This errs on the side of finding too many - a `<<` inside a string just means less of the definition is normalized.
+fn heredocs(line: &str) -> Vec<(String, bool)> {
+    let mut out  = vec![];
+    let mut rest = line;
+
+    while let Some(idx) = rest.find("<<") {
+        rest = &rest[idx + 2..];
+
+        // Here-strings (<<<) don't have a body.
+        if rest.starts_with('<') {
+            rest = rest.trim_start_matches('<');
+            continue
+        }
+
+        let tabs = rest.starts_with('-');
+
+        rest = rest
+            .trim_start_matches('-')
+            .trim_start_matches([' ', '\t']);
+
+        let end = rest
+            .find(|c: char| c.is_whitespace() || ";|&<>()".contains(c))
+            .unwrap_or(rest.len());
+
+        let delimiter: String = rest[..end]
+            .chars()
+            .filter(|c| !['\'', '"', '\\'].contains(c))
+            .collect();
+
+        if !delimiter.is_empty() {
+            out.push((delimiter, tabs));
+        }
+
+        rest = &rest[end..];
+    }
+
+    out
+}
+
+/// The shells Box provides a definition harness for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shell {
+    Fish,
+    Bash,
+    Zsh,
+    Posix,
+}
+
+impl Shell {
+    /// The names accepted by [`Shell::from_name`].
+    pub const SUPPORTED: [&str; 4] = ["fish", "bash", "zsh", "posix"];
+
+    /// Given a name (as passed to `bx init`), fetch the corresponding shell.
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "fish"  => Ok(Self::Fish),
+            "bash"  => Ok(Self::Bash),
+            "zsh"   => Ok(Self::Zsh),
+            "posix" => Ok(Self::Posix),
+            _       => {
+                let err = eyre!("No harness is available for shell {name}")
+                    .note(
+                        format!(
+                            "Supported shells are: {}",
+                            Self::SUPPORTED.join(", ")
+                        )
+                    )
+                    .suggestion("Did you make a typo?");
+
+                Err(err)
+            }
+        }
+    }
+
+    /// Determine the shell to use from a definition's shebang.
+    /// 
+    /// Other POSIX-compatible shells use the POSIX harness; any other interpreter
+    /// has no harness (`None`.)
+    pub fn from_bang(bang: &str) -> Option<Self> {
+        use std::ffi::OsStr;
+
+        let mut words = bang
+            .trim_start_matches("#!")
+            .split_whitespace();
+
+        let mut interpreter = words.next();
+
+        // Look through 'env' (and any options passed to it) for the real interpreter.
+        if interpreter
+            .map(Path::new)
+            .and_then(Path::file_name) == Some(OsStr::new("env"))
+        {
+            interpreter = words.find(|w| !w.starts_with('-'));
+        }
+
+        let name = interpreter
+            .map(Path::new)
+            .and_then(Path::file_name)
+            .and_then(OsStr::to_str);
+
+        match name? {
+            "fish" => Some(Self::Fish),
+            "bash" => Some(Self::Bash),
+            "zsh"  => Some(Self::Zsh),
+            "sh" | "dash" | "ash" | "ksh" | "mksh" | "oksh" | "yash" | "posh" | "busybox" => {
+                Some(Self::Posix)
+            },
+            _ => None
+        }
+    }
+
+    /// Get the harness script for the shell.
+    pub fn harness(self) -> &'static str {
+        match self {
+            Self::Fish  => include_str!("shell/fish.sh"),
+            Self::Bash  => include_str!("shell/bash.sh"),
+            Self::Zsh   => include_str!("shell/zsh.sh"),
+            Self::Posix => include_str!("shell/posix.sh"),
+        }
+    }
+
+    /// Get the name of the shell, as accepted by `bx init`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Fish  => "fish",
+            Self::Bash  => "bash",
+            Self::Zsh   => "zsh",
+            Self::Posix => "posix",
+        }
+    }
+
+    /// Get a human-readable description of definitions using this shell.
+    pub fn description(self) -> &'static str {
+        match self {
+            Self::Fish  => "Fish script",
+            Self::Bash  => "Bash script",
+            Self::Zsh   => "Zsh script",
+            Self::Posix => "POSIX script",
+        }
+    }
+}
+
+/// How a definition is evaluated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// A shell script, evaluated inside a harness.
+    Harness(Shell),
+    /// A Rhai script, evaluated in-process.
+    Rhai,
+    /// Any other executable, which performs directives using `bx` subcommands.
+    Executable,
+}
+
+impl Kind {
+    /// Determine the kind of a definition from its path and shebang.
+    pub fn detect(path: &Path, bang: &str) -> Self {
+        let rhai = path
+            .to_str()
+            .is_some_and(|p| p.ends_with(".box.rhai"));
+
+        if rhai {
+            return Self::Rhai
+        }
+
+        match Shell::from_bang(bang) {
+            Some(shell) => Self::Harness(shell),
+            None => Self::Executable
+        }
+    }
+
+    /// Get the prefix used for metadata lines in definitions of this kind.
+    pub fn metadata_prefix(self) -> &'static str {
+        match self {
+            Self::Rhai => "//~",
+            _ => "#~"
+        }
+    }
+
+    /// Get a human-readable description of definitions of this kind.
+    pub fn description(self) -> &'static str {
+        match self {
+            Self::Harness(shell) => shell.description(),
+            Self::Rhai => "Rhai script",
+            Self::Executable => "Executable",
+        }
+    }
+}
+
+/// File name suffixes recognized as definitions.
+pub const EXTENSIONS: [&str; 2] = [".box.rhai", ".box"];
+
+/// Given a path, determine the name of the definition it contains (file name minus extension),
+/// if it has a recognized extension.
+pub fn definition_name(path: &Path) -> Option<&str> {
+    let file = path
+        .file_name()?
+        .to_str()?;
+
+    EXTENSIONS
+        .iter()
+        .find_map(|e| file.strip_suffix(e))
+        .filter(|n| !n.is_empty())
 }
 
 impl Definition {
     /// Enumerate all definitions.
     pub fn enumerate() -> Result<Definitions> {
         use std::fs;
-        use std::ffi::OsStr;
 
         let dir = definition_directory()?;
 
@@ -58,7 +415,7 @@ impl Definition {
                 continue;
             }
 
-            if entry.path().extension() == OsStr::new("box").into() {
+            if definition_name(&entry.path()).is_some() {
                 out.push(
                     Definition::from_path(entry.path())
                 )            
@@ -94,19 +451,8 @@ impl Definition {
 
     /// Given a name, attempt to find and fetch the corresponding definition.
     pub fn find(name: &str) -> Result<Self> {
-        use std::fs;
-        use std::ffi::OsStr;
-
-        let dir  = definition_directory()?;
-        let stem = OsStr::new(name);
-
-        let entry = fs::read_dir(dir)
-            .context("Fault when starting definition search")?
-            .filter_map(Result::ok)
-            .find(|e| e.path().file_stem() == stem.into());
-
-        if let Some(entry) = entry {
-            Self::from_path(entry.path())
+        if let Some(path) = Self::locate(name)? {
+            Self::from_path(path)
                 .context("Failed to load and parse definition")
         }
         else {
@@ -122,22 +468,36 @@ impl Definition {
         }
     }
 
-    // Given a name, determines whether or not a matching definition exists.
-    pub fn exists(name: &str) -> Result<bool> {
+    /// Given a name, determines the path of the matching definition, if one exists.
+    pub fn locate(name: &str) -> Result<Option<PathBuf>> {
         use std::fs;
 
-        let path = definition_directory()?
-            .join(
-                format!("{name}.box")
+        let dir = definition_directory()?;
+
+        for ext in EXTENSIONS {
+            let path = dir.join(
+                format!("{name}{ext}")
             );
-        
-        fs::exists(path)
-            .map_err(|e| {
-                Report::new(e)
-                    .wrap_err(
-                        format!("Fault when checking if definition ({name}) exists")
-                    )
-            })
+
+            let exists = fs::exists(&path)
+                .map_err(|e| {
+                    Report::new(e)
+                        .wrap_err(
+                            format!("Fault when checking if definition ({name}) exists")
+                        )
+                })?;
+
+            if exists {
+                return Ok(Some(path))
+            }
+        }
+
+        Ok(None)
+    }
+
+    // Given a name, determines whether or not a matching definition exists.
+    pub fn exists(name: &str) -> Result<bool> {
+        Self::locate(name).map(|p| p.is_some())
     }
 
     /// Given a path, attempts to read in its contents and parse it into a well-formed definition.
@@ -178,39 +538,71 @@ impl Definition {
             .context("Encountered an empty definition")?
             .to_owned();
 
-        let meta = data
-            .lines()
-            .filter(|l| l.starts_with("#~"))
-            .fold(String::new(), |mut acc, line| {
-                acc += line.trim_start_matches("#~").trim();
-                acc += "\n";
-                acc
-            });
+        let meta = Metadata::parse(
+            &data,
+            Kind::detect(&path, &bang)
+        )?;
 
-        let meta: Metadata = toml::from_str(&meta)
-            .context("Failed to deserialize TOML frontmatter")
-            .suggestion("Did you make a typo?")?;
+        let raw = seahash::hash(
+            data.as_bytes()
+        );
+
+        let mode = match meta.hash {
+            Some(mode) => mode,
+            None => HashMode::from_env()?
+        };
+
+        let mut hashed = mode
+            .apply(&data, Kind::detect(&path, &bang))
+            .into_owned();
+
+        let mut resolved = String::new();
+
+        // The values of allowed variables can change the output of a hermetic build,
+        // so they're treated as part of the definition.
+        if meta.hermetic {
+            let mut keys = meta.env.clone();
+
+            keys.sort();
+            keys.dedup();
+
+            for key in keys {
+                match std::env::var(&key) {
+                    Ok(value) => resolved += &format!("\0{key}={value}"),
+                    Err(_)    => resolved += &format!("\0{key}"),
+                }
+            }
+        }
+
+        // Pinned base images are folded into the hash, so bumping them triggers a rebuild.
+        let lock = crate::lock::Lockfile::load(
+            path.parent().unwrap_or(&path)
+        )?;
+
+        for pinned in crate::lock::base_images(&data).iter().filter_map(|r| lock.pin(r)) {
+            resolved += pinned;
+        }
+
+        hashed += &resolved;
 
-        
         let hash = seahash::hash(
-            data.as_bytes()
+            hashed.as_bytes()
+        );
+
+        let inputs = seahash::hash(
+            resolved.as_bytes()
         );
 
         let tree = hash;
         
         debug!("Fetched definition from path {path:?}");
 
-        Ok(Self { path, bang, hash, tree, meta })
+        Ok(Self { path, bang, hash, raw, tree, inputs, meta })
     }
 
     /// Get the name of the definition (file name minus extension.)
     pub fn name(&self) -> &str {
-        use std::ffi::OsStr;
-
-        self
-            .path
-            .file_stem()
-            .and_then(OsStr::to_str)
+        definition_name(&self.path)
             .expect("Definition name should be valid UTF-8")
     }
 
@@ -219,8 +611,69 @@ impl Definition {
         &self.meta.depends_on
     }
 
+    /// Get the hash mode used for the definition.
+    pub fn hash_mode(&self) -> Result<HashMode> {
+        match self.meta.hash {
+            Some(mode) => Ok(mode),
+            None => HashMode::from_env()
+        }
+    }
+
+    /// Determine whether or not the provided environment variable is visible to the definition.
+    pub fn allows_var(&self, key: &str) -> bool {
+        !self.meta.hermetic ||
+        HERMETIC_DEFAULTS.contains(&key) ||
+        HERMETIC_PREFIXES.iter().any(|p| key.starts_with(p)) ||
+        self.meta.env.iter().any(|k| k == key)
+    }
+
+    /// Get the environment a hermetic definition should be built with,
+    /// or `None` if it should inherit the full environment.
+    /// 
+    /// Values are passed through untouched, even if they aren't valid UTF-8.
+    pub fn environment(&self) -> Option<Vec<(OsString, OsString)>> {
+        if !self.meta.hermetic {
+            return None
+        }
+
+        let vars = std::env::vars_os()
+            .filter(|(k, _)| k.to_str().is_some_and(|k| self.allows_var(k)))
+            .collect();
+
+        Some(vars)
+    }
+
+    /// Get the build context for the definition.
+    pub fn context(&self) -> BuildContext {
+        BuildContext {
+            path : self.path.to_owned(),
+            hash : format!("{:x}", self.hash),
+            tree : format!("{:x}", self.tree),
+            name : self.name().to_owned(),
+        }
+    }
+
+    /// Get the kind of the definition, which determines how it's evaluated.
+    pub fn kind(&self) -> Kind {
+        Kind::detect(&self.path, &self.bang)
+    }
+
+    /// Get a human-readable description of the definition type.
+    pub fn description(&self) -> &'static str {
+        self.kind().description()
+    }
+
+    /// Get the directory containing the definition.
+    pub fn directory(&self) -> PathBuf {
+        let mut p = self.path.to_owned();
+        p.pop();
+        p
+    }
+
     /// Build the definition.
-    pub fn build(&self) -> Result<()> {
+    /// 
+    /// If `resume` is set, the build resumes from the newest valid checkpoint (if any.)
+    pub fn build(&self, resume: bool) -> Result<()> {
         use std::fs;
         use colored::Colorize;
 
@@ -239,7 +692,11 @@ impl Definition {
         let script = fs::read_to_string(&self.path)
             .context("Fault when reading in definition")?;
 
-        if !script.contains("FROM") {
+        let kind = self.kind();
+
+        // Executable definitions use lowercase 'bx' subcommands,
+        // so this heuristic can't be applied to them.
+        if kind != Kind::Executable && !script.contains("FROM") {
             eprintln!(
                 "{}{} {} {}",
                 "Warning".bold().yellow(),
@@ -249,7 +706,7 @@ impl Definition {
             )
         }
 
-        if !script.contains("COMMIT") {
+        if kind != Kind::Executable && !script.contains("COMMIT") {
             eprintln!(
                 "{}{} {} {}",
                 "Warning".bold().yellow(),
@@ -259,89 +716,298 @@ impl Definition {
             )
         }
 
-        if self.bang.contains("fish") {
-            Command::new("fish")
-                .arg("-C")
-                .arg("bx init fish | source")
-                .arg(&self.path)
-                .env(
-                    "__BOX_BUILD_PATH",
-                    &self.path
-                )
-                .env(
-                    "__BOX_BUILD_DIR",
-                    {
-                        let mut p = self.path.to_owned();
-                        p.pop();
-                        p
-                    }
-                )
-                .env(
-                    "__BOX_BUILD_HASH",
-                    format!("{:x}", self.hash)
-                )
-                .env(
-                    "__BOX_BUILD_TREE",
-                    format!("{:x}", self.tree)
-                )
-                .env(
-                    "__BOX_BUILD_NAME",
-                    self.name()
-                )
-                .spawn_ok()
-                .context("Fault when evaluating Fish-based definition")?;
+        let checkpoints = match kind {
+            // Executable definitions can't skip over directives, so they don't support checkpoints.
+            Kind::Executable => Checkpoints::default(),
+            _ => self.checkpoints(&script, resume)?
+        };
+
+        let result = self.evaluate(kind, &script, &checkpoints);
+
+        if result.is_ok() {
+            self.clean_checkpoints(&checkpoints.keys);
         }
-        else {
-            let script = format!(
-                "source <(bx init posix)\n(\n{script}\n)",
-            );
-            
-            let shell = self
-                .bang
-                .trim_start_matches("#!")
-                // Whitespace after the shebang is valid.
-                .trim();
 
-            if shell.is_empty() {
-                let err = eyre!("Shebang {} is invalid", &self.bang)
-                    .note( "Box could not determine the interpreter path.")
-                    .suggestion("Did you make a typo or forget a shebang?");
+        result
+    }
 
-                return Err(err)
-            };
+    /// Evaluate the provided definition text according to its kind.
+    fn evaluate(&self, kind: Kind, script: &str, checkpoints: &Checkpoints) -> Result<()> {
+        use std::fs;
 
-            Command::new(shell)
-                .arg("-c")
-                .arg(script)
-                .env(
-                    "__BOX_BUILD_PATH",
-                    &self.path
-                )
-                .env(
-                    "__BOX_BUILD_DIR",
-                    {
-                        let mut p = self.path.to_owned();
-                        p.pop();
-                        p
-                    }
-                )
-                .env(
-                    "__BOX_BUILD_HASH",
-                    format!("{:x}", self.hash)
-                )
+        let mut command = match kind {
+            Kind::Rhai => {
+                return crate::script::evaluate(self, script, checkpoints)
+                    .context("Fault when evaluating Rhai script")
+            },
+            Kind::Harness(Shell::Fish) => {
+                let mut c = Command::new("fish");
+
+                c
+                    .arg("-C")
+                    .arg("bx init fish | source")
+                    .arg(&self.path);
+
+                c
+            },
+            Kind::Harness(shell) => {
+                let script = format!(
+                    "source <(bx init {})\n(\n{script}\n)",
+                    shell.name()
+                );
+                
+                // Whitespace after the shebang is valid, as are
+                // arguments to the interpreter (e.g. '/usr/bin/env bash'.)
+                let mut interpreter = self
+                    .bang
+                    .trim_start_matches("#!")
+                    .split_whitespace();
+
+                let Some(program) = interpreter.next() else {
+                    let err = eyre!("Shebang {} is invalid", &self.bang)
+                        .note( "Box could not determine the interpreter path.")
+                        .suggestion("Did you make a typo or forget a shebang?");
+
+                    return Err(err)
+                };
+
+                let mut c = Command::new(program);
+
+                c
+                    .args(interpreter)
+                    .arg("-c")
+                    .arg(script);
+
+                c
+            },
+            Kind::Executable => {
+                use std::os::unix::fs::PermissionsExt;
+
+                let mode = fs::metadata(&self.path)
+                    .context("Fault when checking definition permissions")?
+                    .permissions()
+                    .mode();
+
+                if mode & 0o111 == 0 {
+                    let err = eyre!("Definition {} is not executable", self.name())
+                        .note("Definitions without a shell harness are executed directly.")
+                        .suggestion(
+                            format!(
+                                "Try running 'chmod +x {}'.",
+                                self.path.to_string_lossy()
+                            )
+                        );
+
+                    return Err(err)
+                }
+
+                let mut c = Command::new(&self.path);
+
+                c.current_dir(self.directory());
+
+                c
+            }
+        };
+
+        if let Some(vars) = self.environment() {
+            command
+                .env_clear()
+                .envs(vars);
+        }
+
+        if let Kind::Executable = kind {
+            command.env(
+                "__BOX_BUILD_STATE",
+                state_file(self.name())
+            );
+        }
+
+        command
+            .env(
+                "__BOX_BUILD_PATH",
+                &self.path
+            )
+            .env(
+                "__BOX_BUILD_DIR",
+                self.directory()
+            )
+            .env(
+                "__BOX_BUILD_HASH",
+                format!("{:x}", self.hash)
+            )
+            .env(
+                "__BOX_BUILD_TREE",
+                format!("{:x}", self.tree)
+            )
+            .env(
+                "__BOX_BUILD_NAME",
+                self.name()
+            )
+            .env(
+                "__BOX_BUILD_CHECKPOINTS",
+                checkpoints
+                    .keys
+                    .iter()
+                    .map(|k| format!("{k:x}"))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            );
+
+        if let Some(index) = checkpoints.resume {
+            command
                 .env(
-                    "__BOX_BUILD_TREE",
-                    format!("{:x}", self.tree)
+                    "__BOX_BUILD_RESUME",
+                    index.to_string()
                 )
                 .env(
-                    "__BOX_BUILD_NAME",
-                    self.name()
+                    "__BOX_BUILD_RESUME_IMAGE",
+                    checkpoint_image(self.name(), checkpoints.keys[index - 1])
+                );
+        }
+
+        let result = command
+            .spawn_ok()
+            .context(
+                format!(
+                    "Fault when evaluating {}",
+                    self.description().to_lowercase()
                 )
-                .spawn_ok()
-                .context("Fault when evaluating POSIX-based definition")?;
+            );
+
+        if let Kind::Executable = kind {
+            // The state file may not exist if the definition failed before 'bx from',
+            // so errors here are uninteresting.
+            let _ = fs::remove_file(
+                state_file(self.name())
+            );
+        }
+
+        result
+    }
+
+    /// Compute the checkpoint keys for the provided definition text and, if `resume` is set,
+    /// find the newest checkpoint with an existing image.
+    /// 
+    /// Each key covers the text up to and including its CHECKPOINT directive, as well as the key of
+    /// the previous checkpoint (or, for the first, the combined hash of the definition's dependencies
+    /// and its resolved inputs - so bumping a pin or changing an allowed variable invalidates them all.)
+    pub fn checkpoints(&self, script: &str, resume: bool) -> Result<Checkpoints> {
+        use colored::Colorize;
+
+        // Checkpoints are keyed on the same text as the definition hash,
+        // so they survive the same (insignificant) edits.
+        let script = self
+            .hash_mode()?
+            .apply(script, self.kind());
+
+        // The definition's own hash is cancelled out, so edits after a checkpoint don't invalidate it.
+        let mut seed = (self.tree ^ self.hash).to_le_bytes().to_vec();
+
+        seed.extend(self.inputs.to_le_bytes());
+
+        let mut keys   = vec![];
+        let mut parent = seahash::hash(&seed);
+        let mut offset = 0;
+
+        for line in script.split_inclusive('\n') {
+            offset += line.len();
+
+            // Only unindented checkpoints count, as the harness has no way of telling whether
+            // one inside a loop, function or conditional will run (or how many times.)
+            let is_checkpoint = line
+                .strip_prefix("CHECKPOINT")
+                .is_some_and(|rest| {
+                    !rest.starts_with(|c: char| c.is_alphanumeric() || c == '_')
+                });
+
+            if !is_checkpoint {
+                continue
+            }
+
+            let mut data = script.as_bytes()[..offset].to_vec();
+
+            data.extend(parent.to_le_bytes());
+
+            parent = seahash::hash(&data);
+            keys.push(parent);
+        }
+
+        debug!("Computed checkpoint keys for {}: {keys:x?}", self.name());
+
+        let mut out = Checkpoints {
+            keys,
+            resume: None
+        };
+
+        if !resume {
+            return Ok(out)
+        }
+
+        for (idx, key) in out.keys.iter().enumerate().rev() {
+            let image = checkpoint_image(self.name(), *key);
+
+            if Image::exists(&image)? {
+                eprintln!(
+                    "{} {} {}",
+                    "Resuming from checkpoint".bold().bright_white(),
+                    (idx + 1).to_string().green().bold(),
+                    format!("({image})").bright_black()
+                );
+
+                out.resume = Some(idx + 1);
+                break
+            }
+        }
+
+        Ok(out)
+    }
+
+    /// Remove checkpoint images for this definition that don't match any of the provided keys.
+    /// 
+    /// Failures are logged, but otherwise ignored.
+    pub fn clean_checkpoints(&self, keys: &[u64]) {
+        let repository = checkpoint_repository(self.name());
+
+        let tags = Command::new("podman")
+            .args([
+                "image",
+                "ls",
+                "--format",
+                "{{.Tag}}",
+                "--filter",
+            ])
+            .arg(format!("reference={repository}"))
+            .output_ok();
+
+        let tags = match tags {
+            Ok(tags) => tags,
+            Err(err) => {
+                warn!("Failed to enumerate checkpoints for cleanup: {err}");
+                return
+            }
+        };
+
+        let current: HashSet<_> = keys
+            .iter()
+            .map(|k| format!("{k:x}"))
+            .collect();
+
+        for tag in tags.lines() {
+            if current.contains(tag) {
+                continue
+            }
+
+            debug!("Removing stale checkpoint {repository}:{tag}");
+
+            let result = Command::new("podman")
+                .arg("rmi")
+                .arg(format!("{repository}:{tag}"))
+                .output_ok();
+
+            if let Err(err) = result {
+                warn!("Failed to remove stale checkpoint {repository}:{tag}: {err}");
+            }
         }
-        
-        Ok(())
     }
 
     /// Finds an alternative definition name that is similar to the given name.
@@ -349,8 +1015,6 @@ impl Definition {
     /// This function uses fuzzy matching to find a definition name that is close to the given name.
     /// If no match is found, it returns `None`.
     pub fn alternative(name: &str) -> Option<String> {
-        use std::ffi::OsStr;
-
         use nucleo_matcher::{Matcher, Config};
         use nucleo_matcher::pattern::*;
 
@@ -364,12 +1028,7 @@ impl Definition {
 
         let names: Vec<_> = defs
             .iter()
-            .filter_map(|d| {
-                d
-                    .path
-                    .file_stem()
-                    .and_then(OsStr::to_str)
-            })
+            .map(Definition::name)
             .collect();
 
         let mut matcher = Matcher::new(Config::DEFAULT);
@@ -390,126 +1049,498 @@ impl Definition {
 
 impl Definition {
     /// Create a new definition file with the provided name.
-    pub fn create(name: String) -> Result<()> {
-        use std::fs::File;
-        use dialoguer::Editor;
+    ///
+    /// The editor is seeded with the provided template, or generated from the user's
+    /// answers if `interactive` is set.
+    pub fn create(name: String, template: Option<String>, interactive: bool) -> Result<()> {
+        use dialoguer::Confirm;
+
+        if Self::exists(&name)? {
+            let err = eyre!("Definition {name} already exists")
+                .suggestion("You may want to edit or delete it instead.");
+
+            return Err(err);
+        }
+
+        let template = match (template, interactive) {
+            (_, true) => wizard(&name)?,
+            (Some(t), false) => find(&t, &name)?,
+            (None, false) => Template {
+                text : "#!/bin/bash\n\n".to_owned(),
+                ext  : ".box",
+            }
+        };
+
+        let path = definition_directory()?
+            .join(
+                format!("{name}{}", template.ext)
+            );
+
+        if interactive {
+            println!("{}", template.text);
+
+            let edit = Confirm::new()
+                .with_prompt("Open the definition in your editor before saving?")
+                .default(false)
+                .interact()
+                .context("Fault when asking for user confirmation")?;
+
+            if !edit {
+                return std::fs::write(&path, template.text)
+                    .context("Fault when writing new definition to file");
+            }
+        }
+
+        // The file is only created once the definition is valid, so aborting leaves nothing behind.
+        if let Some(data) = Self::edit_until_valid(&path, &template.text)? {
+            std::fs::write(&path, data)
+                .context("Fault when writing new definition to file")?
+        }
+        else {
+            warn!("Definition creation aborted!");
+            bail!("Definition creation aborted")
+        }
+        
+        Ok(())
+    }
+
+    /// Edit the specified definition file.
+    pub fn edit(name: String) -> Result<()> {
+        let Some(path) = Self::locate(&name)? else {
+            let err = eyre!("Definition {name} does not exist")
+                .suggestion(
+                    format!(
+                        "Box checked in {}",
+                        definition_directory()?.to_string_lossy()
+                    )
+                )                
+                .suggestion("Maybe create it first?");
+
+            return Err(err);
+        };
+
+        let data = std::fs::read_to_string(&path)
+            .context("Fault when reading in definition data for editing")?;
+
+        if let Some(data) = Self::edit_until_valid(&path, &data)? {
+            std::fs::write(&path, data)
+                .context("Fault when writing definition to file")?
+        }
+        else {
+            warn!("Definition edit aborted!");
+            bail!("Definition edit aborted")
+        }
+
+        Ok(())
+    }
+
+    /// Open the provided text in the user's editor, re-opening it (with their changes intact)
+    /// for as long as the result fails validation.
+    /// 
+    /// Returns `None` if the user aborts the edit or discards their changes.
+    fn edit_until_valid(path: &Path, text: &str) -> Result<Option<String>> {
+        use colored::Colorize;
+        use dialoguer::{Editor, Select};
+
+        let mut text = text.to_owned();
+
+        loop {
+            let Some(edited) = Editor::new()
+                .require_save(true)
+                .edit(&text)
+                .context("Fault when editing definition")? 
+            else {
+                return Ok(None)
+            };
+
+            let problems = validate(path, &edited)?;
+
+            if problems.is_empty() {
+                return Ok(Some(edited))
+            }
+
+            eprintln!(
+                "{}{} {}",
+                "Error".bold().red(),
+                ":".bold().bright_white(),
+                "the definition is invalid".bold().bright_white()
+            );
+
+            for problem in &problems {
+                eprintln!("  - {}", problem.replace('\n', "\n    "));
+            }
+
+            text = edited;
+
+            let choice = Select::new()
+                .with_prompt("What now?")
+                .items(&["Re-open the editor", "Save anyway", "Discard changes"])
+                .default(0)
+                .interact()
+                .context("Fault when asking for user choice")?;
+
+            match choice {
+                0 => continue,
+                1 => return Ok(Some(text)),
+                _ => return Ok(None)
+            }
+        }
+    }
+
+    /// Delete the specified definition file.
+    pub fn delete(name: String, yes: bool) -> Result<()> {
+        use dialoguer::Confirm;
+
+        let Some(path) = Self::locate(&name)? else {
+            let err = eyre!("Definition {name} does not exist")
+                .suggestion(
+                    format!(
+                        "Box checked in {}",
+                        definition_directory()?.to_string_lossy()
+                    )
+                )
+                .suggestion("Maybe create it first?");
+
+            return Err(err);
+        };
+
+        if !yes {
+            let confirm = Confirm::new()
+                .with_prompt(
+                    format!("Are you sure you want to remove the definition {name:?}")
+                )
+                .interact()
+                .context("Fault when asking for user confirmation")?;
+
+            if !confirm {
+                return Ok(())
+            }
+        }
+
+        std::fs::remove_file(path)
+            .context("Fault when removing definition")?;
+
+        Ok(())
+    }
+
+    /// Rename a definition, updating `depends_on` references to it in all other definitions.
+    pub fn rename(old: String, new: String) -> Result<()> {
+        use colored::Colorize;
+
+        let (path, ext) = Self::locate_for_copy(&old, &new)?;
+
+        let target = definition_directory()?
+            .join(
+                format!("{new}{ext}")
+            );
+
+        std::fs::rename(&path, &target)
+            .context("Fault when renaming definition")?;
+
+        eprintln!(
+            "{} {} {} {}",
+            "Renamed".bold().bright_white(),
+            old.yellow().bold(),
+            "to".bold().bright_white(),
+            new.green().bold()
+        );
+
+        for def in Self::enumerate()? {
+            let data = std::fs::read_to_string(&def.path)
+                .context("Fault when reading in definition")?;
+
+            // FROM references aren't rewritten, as the old image sticks around until the definition is rebuilt.
+            if def.name() != new && directive_mentions(&data, "FROM", &old) {
+                eprintln!(
+                    "{}{} {} {} {}",
+                    "Warning".bold().yellow(),
+                    ": definition".bold().bright_white(),
+                    def.name().green().bold(),
+                    "still uses the image of".bold().bright_white(),
+                    old.yellow().bold()
+                );
+            }
+
+            let Some(data) = rename_dependency(&data, def.kind(), &old, &new) else {
+                continue
+            };
+
+            std::fs::write(&def.path, data)
+                .context("Fault when writing definition to file")?;
+
+            eprintln!(
+                "{} {}",
+                "Updated references in".bold().bright_white(),
+                def.name().green().bold()
+            );
+        }
+
+        warn_commit_name(&target, &new, &old)?;
+
+        let images = Image::enumerate()?
+            .into_iter()
+            .filter(|i| i.annotation("box.name") == Some(&old))
+            .count();
+
+        let containers = Container::enumerate()?
+            .into_iter()
+            .filter(|c| c.annotation("box.name") == Some(&old))
+            .count();
+
+        if images + containers != 0 {
+            eprintln!(
+                "{}{} {} {}",
+                "Warning".bold().yellow(),
+                ":".bold().bright_white(),
+                format!("{images} image(s) and {containers} container(s) are still named").bold().bright_white(),
+                old.yellow().bold()
+            );
+
+            eprintln!(
+                "Run {} to rebuild under the new name, then {} and {} to clean up.",
+                format!("bx build {new}").yellow(),
+                format!("bx down {old}").yellow(),
+                "bx prune".yellow()
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Copy a definition under a new name.
+    pub fn clone(src: String, dst: String) -> Result<()> {
+        use colored::Colorize;
+
+        let (path, ext) = Self::locate_for_copy(&src, &dst)?;
+
+        let target = definition_directory()?
+            .join(
+                format!("{dst}{ext}")
+            );
+
+        // Permissions are copied too, which matters for definitions without a harness.
+        std::fs::copy(&path, &target)
+            .context("Fault when copying definition")?;
+
+        eprintln!(
+            "{} {} {} {}",
+            "Cloned".bold().bright_white(),
+            src.yellow().bold(),
+            "to".bold().bright_white(),
+            dst.green().bold()
+        );
+
+        warn_commit_name(&target, &dst, &src)
+    }
+
+    /// Locate the source definition of a rename or clone (and its extension),
+    /// ensuring the destination name is valid and not already taken.
+    fn locate_for_copy(src: &str, dst: &str) -> Result<(PathBuf, &'static str)> {
+        let Some(path) = Self::locate(src)? else {
+            let suggestion = match Self::alternative(src) {
+                Some(m) => format!("Did you mean '{}'?", m),
+                None => "Did you make a typo?".to_string(),
+            };
+
+            let err = eyre!("Definition {src} does not exist")
+                .suggestion(suggestion);
+
+            return Err(err);
+        };
 
-        if Self::exists(&name)? {
-            let err = eyre!("Definition {name} already exists")
-                .suggestion("You may want to edit or delete it instead.");
+        if dst.is_empty() || dst.contains(['/', '\0']) || dst.starts_with('.') {
+            let err = eyre!("{dst:?} is not a valid definition name")
+                .note("Definition names are used as file names, so they can't contain slashes or start with a dot.");
 
             return Err(err);
         }
 
-        let path = definition_directory()?
-            .join(
-                format!("{name}.box")
-            );
-
-        File::create(&path)
-            .context("Fault when creating definition file")?;
+        if Self::exists(dst)? {
+            let err = eyre!("Definition {dst} already exists")
+                .suggestion("Pick another name, or delete the existing definition first.");
 
-        if let Some(data) = Editor::new()
-            .require_save(true)
-            .edit("#!/bin/bash\n\n")
-            .context("Fault when editing new definition")?
-        {
-            std::fs::write(&path, data)
-                .context("Fault when writing new definition to file")?
+            return Err(err);
         }
-        else {
-            warn!("Definition creation aborted!");
 
-            std::fs::remove_file(&path)
-                .context("Fault when removing unwanted definition file")?;
+        let ext = EXTENSIONS
+            .iter()
+            .find(|e| path.to_string_lossy().ends_with(*e))
+            .expect("Located definitions should have a recognized extension");
 
-            bail!("Definition creation aborted")
-        }
-        
-        Ok(())
+        Ok((path, ext))
     }
+}
 
-    /// Edit the specified definition file.
-    pub fn edit(name: String) -> Result<()> {
-        use dialoguer::Editor;
+/// Rewrite references to a renamed definition in the `depends_on` metadata of the provided text,
+/// returning `None` if there were none.
+fn rename_dependency(data: &str, kind: Kind, old: &str, new: &str) -> Option<String> {
+    let prefix = kind.metadata_prefix();
 
-        if !Self::exists(&name)? {
-            let err = eyre!("Definition {name} does not exist")
-                .suggestion(
-                    format!(
-                        "Box checked in {}",
-                        definition_directory()?.to_string_lossy()
-                    )
-                )                
-                .suggestion("Maybe create it first?");
+    let mut out      = String::with_capacity(data.len());
+    let mut changed  = false;
+    let mut in_array = false;
 
-            return Err(err);
+    for line in data.split_inclusive('\n') {
+        let is_metadata = line.starts_with(prefix);
+
+        // Arrays can span multiple metadata lines, so track whether we're still inside one.
+        if is_metadata && line.contains("depends_on") {
+            in_array = true;
         }
 
-        let path = definition_directory()?
-            .join(
-                format!("{name}.box")
-            );
+        if is_metadata && in_array {
+            let replaced = line
+                .replace(&format!("\"{old}\""), &format!("\"{new}\""))
+                .replace(&format!("'{old}'"), &format!("'{new}'"));
 
-        let data = std::fs::read_to_string(&path)
-            .context("Fault when reading in definition data for editing")?;
+            changed |= replaced != line;
+            out += &replaced;
 
-        if let Some(data) = Editor::new()
-            .require_save(true)
-            .edit(&data)
-            .context("Fault when editing definition")?
-        {
-            std::fs::write(&path, data)
-                .context("Fault when writing definition to file")?
+            if line.contains(']') {
+                in_array = false;
+            }
         }
         else {
-            warn!("Definition edit aborted!");
-            bail!("Definition edit aborted")
+            out += line;
         }
+    }
 
-        Ok(())
+    changed.then_some(out)
+}
+
+/// Determine whether or not any invocation of a directive in the provided text mentions an image name.
+fn directive_mentions(data: &str, directive: &str, name: &str) -> bool {
+    data
+        .lines()
+        .map(str::trim_start)
+        .filter(|l| l.starts_with(directive))
+        .any(|l| {
+            l
+                .split(|c: char| c.is_whitespace() || "()[]\"',;:/".contains(c))
+                .any(|t| t == name)
+        })
+}
+
+/// Warn if a definition commits its image under a name other than its own,
+/// as is likely after it has been renamed or cloned.
+fn warn_commit_name(path: &Path, name: &str, previous: &str) -> Result<()> {
+    use colored::Colorize;
+
+    let data = std::fs::read_to_string(path)
+        .context("Fault when reading in definition")?;
+
+    if directive_mentions(&data, "COMMIT", previous) {
+        eprintln!(
+            "{}{} {} {} {}",
+            "Warning".bold().yellow(),
+            ": definition".bold().bright_white(),
+            name.green().bold(),
+            "still commits its image as".bold().bright_white(),
+            previous.yellow().bold()
+        );
+
+        eprintln!(
+            "Box uses the definition name to find its image, so update its {} directive.",
+            "COMMIT".yellow()
+        );
     }
 
-    /// Delete the specified definition file.
-    pub fn delete(name: String, yes: bool) -> Result<()> {
-        use dialoguer::Confirm;
+    Ok(())
+}
 
-        if !Self::exists(&name)? {
-            let err = eyre!("Definition {name} does not exist")
-                .suggestion(
-                    format!(
-                        "Box checked in {}",
-                        definition_directory()?.to_string_lossy()
-                    )
-                )
-                .suggestion("Maybe create it first?");
+/// Check the text of a definition (to be) stored at the provided path for problems
+/// that would otherwise only surface at build time, returning a description of each.
+pub fn validate(path: &Path, data: &str) -> Result<Vec<String>> {
+    let mut problems = vec![];
 
-            return Err(err);
+    let Some(name) = definition_name(path) else {
+        bail!("Definition path {} has no recognized extension", path.to_string_lossy())
+    };
+
+    let bang = data
+        .lines()
+        .next()
+        .unwrap_or_default();
+
+    let kind = Kind::detect(path, bang);
+
+    if kind != Kind::Rhai && !bang.starts_with("#!") {
+        problems.push(
+            "The first line must be a shebang (such as #!/bin/bash)".to_owned()
+        );
+    }
+
+    let meta = match Metadata::parse(data, kind) {
+        Ok(meta) => meta,
+        Err(err) => {
+            // The root cause is the TOML error, which is the useful part.
+            problems.push(
+                format!("Invalid metadata: {}", err.root_cause().to_string().trim())
+            );
+
+            return Ok(problems)
         }
+    };
 
-        let path = definition_directory()?
-            .join(
-                format!("{name}.box")
+    let mut visited = HashSet::new();
+
+    for dep in &meta.depends_on {
+        if dep == name {
+            problems.push(
+                format!("{name} depends on itself")
             );
 
-        if !yes {
-            let confirm = Confirm::new()
-                .with_prompt(
-                    format!("Are you sure you want to remove the definition {name:?}")
-                )
-                .interact()
-                .context("Fault when asking for user confirmation")?;
+            continue
+        }
 
-            if !confirm {
-                return Ok(())
-            }
+        if !Definition::exists(dep)? {
+            problems.push(
+                format!("Dependency {dep} does not exist")
+            );
+
+            continue
         }
 
-        std::fs::remove_file(path)
-            .context("Fault when removing definition")?;
+        let mut chain = vec![name.to_owned(), dep.to_owned()];
 
-        Ok(())
+        if find_cycle(name, &mut chain, &mut visited) {
+            problems.push(
+                format!("Dependency cycle: {}", chain.join(" -> "))
+            );
+        }
+    }
+
+    Ok(problems)
+}
+
+/// Walk the dependencies of the last definition in `chain`, returning true (with `chain` describing the cycle)
+/// if they lead back to `root`.
+fn find_cycle(root: &str, chain: &mut Vec<String>, visited: &mut HashSet<String>) -> bool {
+    let current = chain
+        .last()
+        .expect("Chain should not be empty")
+        .to_owned();
+
+    if !visited.insert(current.clone()) {
+        return false
+    }
+
+    // Definitions that fail to load aren't the concern of the one being validated.
+    let Ok(def) = Definition::find(&current) else {
+        return false
+    };
+
+    for dep in def.depends_on() {
+        chain.push(dep.to_owned());
+
+        if dep == root || find_cycle(root, chain, visited) {
+            return true
+        }
+
+        chain.pop();
     }
+
+    false
 }
 
 /// Determines the directory to use for definitions.
@@ -563,11 +1594,80 @@ pub fn definition_directory() -> Result<PathBuf> {
     }
 }
 
+/// Checkpoint state for a single build of a definition.
+#[derive(Debug, Default)]
+pub struct Checkpoints {
+    /// The key of each CHECKPOINT directive in the definition, in order.
+    pub keys   : Vec<u64>,
+    /// The (1-based) index of the checkpoint to resume from, if any.
+    pub resume : Option<usize>,
+}
+
+/// Determines the repository used for a definition's checkpoint images.
+pub fn checkpoint_repository(name: &str) -> String {
+    // Image references must be lowercase.
+    format!(
+        "localhost/box-checkpoint/{}",
+        name.to_lowercase()
+    )
+}
+
+/// Determines the checkpoint image for a definition and checkpoint key.
+pub fn checkpoint_image(name: &str, key: u64) -> String {
+    format!(
+        "{}:{key:x}",
+        checkpoint_repository(name)
+    )
+}
+
+/// Identifying information for a definition being built, which Box
+/// applies to its working container as annotations.
+#[derive(Debug, Clone)]
+pub struct BuildContext {
+    pub path : PathBuf,
+    pub hash : String,
+    pub tree : String,
+    pub name : String,
+}
+
+impl BuildContext {
+    /// Fetch the context of the current build from the `__BOX_BUILD_*` environment variables.
+    pub fn from_env() -> Result<Self> {
+        let var = |key: &str| {
+            std::env::var(key).map_err(|_| {
+                eyre!("Directive must be invoked inside of a build context")
+                    .note(format!("{key} is not set."))
+                    .suggestion("Directives only work inside definitions being evaluated by 'bx build'.")
+            })
+        };
+
+        Ok(Self {
+            path : var("__BOX_BUILD_PATH")?.into(),
+            hash : var("__BOX_BUILD_HASH")?,
+            tree : var("__BOX_BUILD_TREE")?,
+            name : var("__BOX_BUILD_NAME")?,
+        })
+    }
+}
+
+/// Determines the path of the file used to track build state for definitions
+/// without a harness (most importantly, the working container created by `bx from`.)
+pub fn state_file(name: &str) -> PathBuf {
+    std::env::temp_dir()
+        .join(
+            format!("box-build-{}-{name}", std::process::id())
+        )
+}
+
 /// Given a slice of definition names, attempt to fetch and build them.
 /// 
 /// - Alternately, if `all` is true, this function will enumerate all definitions and attempt to build them.
 /// - By default, Box skips building a definition if both it and its dependencies are unchanged; `force` overrides this behavior.
-pub fn build_set(defs: &[String], all: bool, force: bool) -> Result<()> {   
+/// - Builds resume from the newest valid checkpoint unless `no_cache` is set.
+/// - A summary is printed once the set is finished; if `report_path` is provided, it's also written there as JSON.
+pub fn build_set(defs: &[String], all: bool, force: bool, no_cache: bool, report_path: Option<&Path>) -> Result<()> {   
+    use std::time::Instant;
+
     use colored::Colorize;
     
     use petgraph::Graph;
@@ -718,38 +1818,35 @@ pub fn build_set(defs: &[String], all: bool, force: bool) -> Result<()> {
         .map_err(|e| eyre!{"{e:?}"})
         .context("Cycle detected in definition dependency graph")?;
         
-    if force {
-        for idx in topo {
-            graph[idx].build()?;
-        }
-
-        debug!("Finished building definition set!");
-
-        return Ok(());
-    }
+    let started     = Instant::now();
+    let mut records = vec![];
+    let mut failure = None;
 
     let to_u64 = |s| u64::from_str_radix(s, 16)
         .expect("Hash annotation should be a 64-bit hexadecimal number");
 
-    let path_hash: HashMap<_, _> = Image::enumerate()
-        .context("Fault when enumerating images for change detection")?
-        .iter()
-        .map(|i| 
-            (
-                i.annotation("box.path")
-                    .map(PathBuf::from)
-                    .expect("Path annotation should be set"),
+    let path_hash: HashMap<_, _> = match force {
+        true  => HashMap::new(),
+        false => Image::enumerate()
+            .context("Fault when enumerating images for change detection")?
+            .iter()
+            .map(|i| 
                 (
-                    i.annotation("box.hash")
-                        .map(to_u64)
-                        .expect("Hash annotation should be set"),
-                    i.annotation("box.tree")
-                        .map(to_u64)
-                        .expect("Tree hash annotation should be set")
+                    i.annotation("box.path")
+                        .map(PathBuf::from)
+                        .expect("Path annotation should be set"),
+                    (
+                        i.annotation("box.hash")
+                            .map(to_u64)
+                            .expect("Hash annotation should be set"),
+                        i.annotation("box.tree")
+                            .map(to_u64)
+                            .expect("Tree hash annotation should be set")
+                    )
                 )
             )
-        )
-        .collect();
+            .collect()
+    };
 
     debug!("Path -> Hash mapping computed:\n{path_hash:?}");
 
@@ -758,28 +1855,346 @@ pub fn build_set(defs: &[String], all: bool, force: bool) -> Result<()> {
 
         debug!("Inspecting... {def:?}");
 
-        // If no image with a corresponding path exists, build.
-        let Some(hashes) = path_hash.get(&def.path) else {
-            def.build()?;
+        // Nothing is attempted after a failure, but it's still recorded
+        // so reports can tell it apart from definitions that weren't requested.
+        if failure.is_some() {
+            records.push(
+                BuildRecord::new(def, BuildStatus::Cancelled, None)
+            );
+
             continue
+        }
+
+        // Build if forced, if no image with a corresponding path exists,
+        // or if either hash has changed.
+        let changed = force || match path_hash.get(&def.path) {
+            Some(hashes) => {
+                debug!("Hashes: {hashes:?}");
+
+                let (own, tree) = hashes;
+
+                *own != def.hash || *tree != def.tree
+            },
+            None => true
         };
 
-        debug!("Hashes: {hashes:?}");
+        if !changed {
+            eprintln!(
+                "{} {} (unchanged)",
+                "Skipped definition".bright_white().bold(),
+                def.name().yellow().bold(),
+            );
+
+            records.push(
+                BuildRecord::new(def, BuildStatus::Skipped, None)
+            );
 
-        let (own, tree) = hashes;
-        
-        if *own != def.hash || *tree != def.tree {
-            def.build()?;
             continue
         }
 
-        // If we got here, the build was skipped.
+        let start  = Instant::now();
+        let result = def.build(!no_cache);
+
+        let status = match result {
+            Ok(_)  => BuildStatus::Built,
+            Err(_) => BuildStatus::Failed,
+        };
+
+        records.push(
+            BuildRecord::new(def, status, Some(start.elapsed()))
+        );
+
+        if let Err(err) = result {
+            failure = Some(err);
+        }
+    }
+
+    debug!("Finished building definition set!");
+
+    // Builds produce new images, so anything enumerated beforehand is stale.
+    invalidate_cache();
+
+    let mut report = BuildReport {
+        elapsed     : started.elapsed().as_secs_f64(),
+        definitions : records,
+    };
+
+    report.resolve_images();
+
+    // Built images are kept around (up to a limit) so they can be rolled back to.
+    for record in &report.definitions {
+        let Some(image) = record.image.as_deref() else {
+            continue
+        };
+
+        if record.status != BuildStatus::Built {
+            continue
+        }
+
+        if let Err(err) = crate::history::record(&record.name, &record.tree, image) {
+            eprintln!(
+                "{}{} {} {}",
+                "Warning".bold().yellow(),
+                ": failed to record version of".bold().bright_white(),
+                record.name.bold().green(),
+                format!("({err})").bright_black()
+            );
+        }
+    }
+
+    report.summarize();
+
+    if let Some(path) = report_path {
+        report.write(path)?;
+    }
+
+    match failure {
+        Some(err) => Err(err),
+        None => Ok(())
+    }
+}
+
+/// The outcome of building a single definition.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BuildStatus {
+    Built,
+    Skipped,
+    Failed,
+    /// Not attempted, as an earlier definition failed to build.
+    Cancelled,
+}
+
+/// A record of a single definition's build, for inclusion in a [`BuildReport`].
+#[derive(Debug, Serialize)]
+pub struct BuildRecord {
+    pub name     : String,
+    pub path     : PathBuf,
+    pub status   : BuildStatus,
+    /// Duration of the build in seconds; `None` if it was skipped or cancelled.
+    pub duration : Option<f64>,
+    /// ID of the resulting image, if one could be found.
+    pub image    : Option<String>,
+    pub hash     : String,
+    /// Hash of the definition's unmodified text, which differs from `hash` in normalized mode.
+    pub raw      : String,
+    pub tree     : String,
+}
+
+impl BuildRecord {
+    fn new(def: &Definition, status: BuildStatus, duration: Option<Duration>) -> Self {
+        Self {
+            name     : def.name().to_owned(),
+            path     : def.path.to_owned(),
+            status,
+            duration : duration.map(|d| d.as_secs_f64()),
+            image    : None,
+            hash     : format!("{:x}", def.hash),
+            raw      : format!("{:x}", def.raw),
+            tree     : format!("{:x}", def.tree),
+        }
+    }
+}
+
+/// Summary of a [`build_set`] invocation.
+#[derive(Debug, Serialize)]
+pub struct BuildReport {
+    /// Total elapsed time in seconds.
+    pub elapsed     : f64,
+    pub definitions : Vec<BuildRecord>,
+}
+
+impl BuildReport {
+    /// Fill in the ID of the image corresponding to each definition that was built or skipped.
+    fn resolve_images(&mut self) {
+        let images = match Image::enumerate() {
+            Ok(images) => images,
+            Err(err) => {
+                warn!("Failed to enumerate images for build report: {err}");
+                return
+            }
+        };
+
+        for record in &mut self.definitions {
+            if let BuildStatus::Failed | BuildStatus::Cancelled = record.status {
+                continue
+            }
+
+            record.image = images
+                .iter()
+                .find(|i| {
+                    i.annotation("box.path") == record.path.to_str() &&
+                    i.annotation("box.tree") == Some(&record.tree)
+                })
+                .map(|i| i.id.to_owned());
+        }
+    }
+
+    /// Print a summary table to standard error, highlighting the slowest definitions.
+    fn summarize(&self) {
+        use colored::Colorize;
+        use comfy_table::{Attribute, Cell, Color, Table};
+        use comfy_table::presets::NOTHING;
+
+        if self.definitions.is_empty() {
+            return
+        }
+
+        let mut durations: Vec<_> = self
+            .definitions
+            .iter()
+            .filter_map(|r| r.duration)
+            .collect();
+
+        durations.sort_by(|a, b| b.total_cmp(a));
+
+        // Highlight the three slowest builds (or just the slowest, for small sets),
+        // but only when there's something to compare against.
+        let threshold = match durations.len() {
+            0 | 1 => f64::INFINITY,
+            2..=3 => durations[0],
+            _     => durations[2]
+        };
+
+        let rows = self
+            .definitions
+            .iter()
+            .map(|r| {
+                let status = match r.status {
+                    BuildStatus::Built     => Cell::new("Built").fg(Color::Green),
+                    BuildStatus::Skipped   => Cell::new("Skipped").fg(Color::Yellow),
+                    BuildStatus::Failed    => Cell::new("Failed").fg(Color::Red),
+                    BuildStatus::Cancelled => Cell::new("Cancelled").fg(Color::DarkGrey),
+                };
+
+                let duration = match r.duration {
+                    Some(d) if d >= threshold => {
+                        Cell::new(format_duration(d))
+                            .fg(Color::Yellow)
+                            .add_attribute(Attribute::Bold)
+                    },
+                    Some(d) => Cell::new(format_duration(d)),
+                    None => Cell::new("-"),
+                };
+
+                let image = r
+                    .image
+                    .as_deref()
+                    .map(|i| &i[..i.len().min(12)])
+                    .unwrap_or("-");
+
+                vec![
+                    Cell::new(&r.name),
+                    status,
+                    duration,
+                    Cell::new(image),
+                ]
+            });
+
+        let mut table = Table::new();
+
+        table
+            .load_preset(NOTHING)
+            .set_header(["Definition", "Status", "Duration", "Image"])
+            .add_rows(rows);
+
+        eprintln!();
+        eprintln!("{table}");
+        eprintln!();
         eprintln!(
-            "{} {} (unchanged)",
-            "Skipped definition".bright_white().bold(),
-            def.name().yellow().bold(),
-        )
+            "{} {}",
+            "Finished in".bold().bright_white(),
+            format_duration(self.elapsed).green().bold()
+        );
     }
 
-    Ok(())
-}
\ No newline at end of file
+    /// Write the report to the provided path as JSON.
+    fn write(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)
+            .context("Fault when serializing build report")?;
+
+        std::fs::write(path, json)
+            .context(
+                format!(
+                    "Fault when writing build report to {}",
+                    path.to_string_lossy()
+                )
+            )
+    }
+}
+
+/// Format a duration in seconds for humans (e.g. `1m 32.1s`.)
+fn format_duration(secs: f64) -> String {
+    let minutes = (secs / 60.0).floor();
+
+    if minutes >= 1.0 {
+        format!("{minutes}m {:.1}s", secs - minutes * 60.0)
+    }
+    else {
+        format!("{secs:.1}s")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Write a definition and lock file to a fresh directory, returning the definition's path.
+    fn write(dir: &str, pin: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(
+            format!("box-test-{dir}-{}", std::process::id())
+        );
+
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("dev.box"),
+            "#!/bin/sh\nFROM fedora:latest\nCHECKPOINT\nRUN true\nCHECKPOINT\n"
+        ).unwrap();
+        std::fs::write(
+            dir.join("box.lock"),
+            format!("[images]\n\"fedora:latest\" = \"{pin}\"\n")
+        ).unwrap();
+
+        dir.join("dev.box")
+    }
+
+    #[test]
+    fn checkpoint_keys_follow_pins() {
+        let keys = |dir: &str, pin: &str| {
+            let path   = write(dir, pin);
+            let script = std::fs::read_to_string(&path).unwrap();
+
+            Definition::from_path(path)
+                .unwrap()
+                .checkpoints(&script, false)
+                .unwrap()
+                .keys
+        };
+
+        let old = keys("pin-old", "fedora@sha256:aaaa");
+        let new = keys("pin-new", "fedora@sha256:bbbb");
+
+        assert_eq!(old.len(), 2);
+        assert_eq!(old, keys("pin-same", "fedora@sha256:aaaa"));
+        assert!(old.iter().all(|k| !new.contains(k)));
+    }
+
+    #[test]
+    fn normalize_keeps_heredoc_bodies() {
+        let script = "#!/bin/sh\n# comment\ncat > a <<EOF\n# setting\n\nEOF\n\ncat > b <<-'END' ; true\n\t# other  \n\tEND\n# gone\n";
+
+        assert_eq!(
+            normalize(script, Kind::Harness(Shell::Posix)),
+            "#!/bin/sh\ncat > a <<EOF\n# setting\n\nEOF\ncat > b <<-'END' ; true\n\t# other  \n\tEND\n"
+        );
+    }
+
+    #[test]
+    fn heredocs_skip_here_strings() {
+        assert_eq!(heredocs("cat <<< \"$x\""), vec![]);
+        assert_eq!(
+            heredocs("paste <<A <<-\"B\""),
+            vec![("A".to_owned(), false), ("B".to_owned(), true)]
+        );
+    }
+}
@@ -1,4 +1,4 @@
-use clap::{Args, Parser, Subcommand};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 
 #[derive(Debug, Parser)]
 #[command(version, about)]
@@ -19,6 +19,14 @@ pub struct ContainerSet {
     pub all: bool,
 }
 
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum ExportFormat {
+    /// A Kubernetes Pod manifest.
+    Kube,
+    /// A compose service.
+    Compose,
+}
+
 #[derive(Debug, Subcommand)]
 pub enum Command {
     /// Compile definitions into container images.
@@ -31,17 +39,35 @@ pub enum Command {
         /// Whether or not to ignore unchanged definitions.
         #[arg(short, long)]
         force: bool,
+        /// Whether or not to ignore checkpoints and build from scratch.
+        #[arg(long)]
+        no_cache: bool,
+        /// Write a report of the build to a file (supported formats: json).
+        #[arg(long, num_args = 2, value_names = ["FORMAT", "FILE"])]
+        report: Option<Vec<String>>,
     },
+    /// Copy a container definition under a new name.
+    Clone { src: String, dst: String },
     /// List all managed containers (alias: ls)
     #[clap(alias = "ls")]
     Containers,
     /// Create a new container definition.
-    Create { name: String },
+    Create {
+        name: String,
+        /// A template to start from (see 'bx templates'.)
+        #[arg(short, long)]
+        template: Option<String>,
+        /// Generate the definition by answering a few questions.
+        #[arg(short, long, conflicts_with = "template")]
+        interactive: bool,
+    },
     /// List all managed definitions (alias: lsd)
     #[clap(alias = "lsd")]
     Definitions,
     /// Delete a container definition.
     Delete { name: String, #[arg(short, long)] yes: bool },
+    /// Show how a definition has changed since its image was built.
+    Diff { name: String },
     /// Output the directory currently being used for definitions.
     Directory,
     /// Remove managed container(s).
@@ -49,35 +75,115 @@ pub enum Command {
     /// Edit an existing container definition.
     Edit   { name: String },
     /// Invoke $SHELL inside a container.
-    Enter  { name: String },
+    Enter  {
+        name: String,
+        /// Fail if the container doesn't exist, rather than offering to create it.
+        #[arg(long)]
+        no_create: bool,
+        /// Set or forward an environment variable (can be repeated.)
+        #[arg(short, long, value_name = "KEY[=VAL]")]
+        env: Vec<String>,
+    },
     /// Execute a command inside a new ephemeral container.
     Ephemeral {
         /// The name or ID of the image to use.
         name: String,
-        /// The program to execute.
-        path: String,
-        /// Arguments to the program, if any.
-        #[arg(allow_hyphen_values = true)]
-        args: Vec<String>,
+        /// Allocate a pseudo-TTY, even if standard input or output isn't a terminal.
+        #[arg(long, overrides_with = "no_tty")]
+        tty: bool,
+        /// Never allocate a pseudo-TTY.
+        #[arg(long)]
+        no_tty: bool,
+        /// The program to execute, followed by its arguments.
+        /// 
+        /// Everything after the program is passed to it, even if it looks like one of Box's flags.
+        #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true, value_name = "PROGRAM")]
+        command: Vec<String>,
+    },
+    /// Export the runtime configuration of a managed image as Kubernetes YAML or compose.
+    Export {
+        /// The name or ID of the image to use.
+        name: String,
+        /// The format to export to.
+        #[arg(short, long, value_enum)]
+        format: ExportFormat,
     },
     /// Execute a command inside a container.
     Exec {
         /// The name of the container.
         name: String,
-        /// The program to execute.
-        path: String,
-        /// Arguments to the program, if any.
-        #[arg(allow_hyphen_values = true)]
-        args: Vec<String>,
+        /// Fail if the container doesn't exist, rather than offering to create it.
+        #[arg(long)]
+        no_create: bool,
+        /// Set or forward an environment variable (can be repeated.)
+        #[arg(short, long, value_name = "KEY[=VAL]")]
+        env: Vec<String>,
+        /// Allocate a pseudo-TTY, even if standard input or output isn't a terminal.
+        #[arg(long, overrides_with = "no_tty")]
+        tty: bool,
+        /// Never allocate a pseudo-TTY.
+        #[arg(long)]
+        no_tty: bool,
+        /// The program to execute, followed by its arguments.
+        /// 
+        /// Everything after the program is passed to it, even if it looks like one of Box's flags.
+        #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true, value_name = "PROGRAM")]
+        command: Vec<String>,
     },
+    /// List the recorded versions of a definition's image.
+    History { name: String },
+    /// Show a definition along with its image, runtime configuration and containers.
+    Inspect {
+        name: String,
+        /// Output JSON instead of tables.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Pin the base images of definitions to digests in a lock file.
+    Lock {
+        /// The definitions to lock; defaults to all of them.
+        defs: Vec<String>,
+        /// Whether or not to re-resolve images that are already pinned.
+        #[arg(short, long)]
+        update: bool,
+    },
+    /// List managed images whose base images have newer versions available.
+    Outdated,
+    /// Remove superseded images, checkpoints and leftover working containers.
+    Prune { #[arg(short, long)] yes: bool },
+    /// Rename a container definition, updating references to it.
+    Rename { old: String, new: String },
     /// Restart managed container(s).
     Restart (ContainerSet),
     /// Remove and re-create managed container(s).
     Reup    (ContainerSet),
+    /// Make an older version of a definition's image current again.
+    Rollback {
+        /// The name of the definition.
+        name: String,
+        /// The version to roll back to, as listed by 'bx history'; defaults to the previous one.
+        version: Option<usize>,
+        /// Whether or not to re-create the definition's container from the rolled back image.
+        #[arg(short, long)]
+        reup: bool,
+    },
     /// Start managed container(s).
     Start   (ContainerSet),
     /// Stop managed containers(s).
     Stop    (ContainerSet),
+    /// Generate a Podman Quadlet unit from a managed image.
+    Systemd {
+        /// The name or ID of the image to use.
+        name: String,
+        /// Print the unit to standard output instead of installing it.
+        #[arg(short, long)]
+        stdout: bool,
+        /// Whether or not to overwrite an existing unit.
+        #[arg(short, long)]
+        replace: bool,
+    },
+    /// List the templates available to 'bx create'.
+    Templates,
     /// Create managed container(s).
     Up {
         /// One or more images; can use names and IDs interchangeably.
@@ -99,5 +205,84 @@ pub enum Command {
         operation : String,
         #[arg(allow_hyphen_values = true)]
         args      : Vec<String>,
+    },
+
+    // Directive protocol for definitions without a harness.
+    #[clap(hide = true)]
+    From {
+        #[arg(allow_hyphen_values = true)]
+        args : Vec<String>,
+    },
+    #[clap(hide = true)]
+    Run {
+        #[arg(allow_hyphen_values = true)]
+        args : Vec<String>,
+    },
+    #[clap(hide = true)]
+    Add {
+        #[arg(allow_hyphen_values = true)]
+        args : Vec<String>,
+    },
+    #[clap(hide = true)]
+    Cfg {
+        operation : String,
+        #[arg(allow_hyphen_values = true)]
+        args      : Vec<String>,
+    },
+    #[clap(hide = true)]
+    Commit {
+        #[arg(allow_hyphen_values = true)]
+        args : Vec<String>,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    #[test]
+    fn exec_passes_flags_after_the_program_through() {
+        let cli = Cli::try_parse_from(["bx", "exec", "dev", "grep", "-e", "foo", "--tty", "--no-create", "file"]).unwrap();
+
+        let Command::Exec { env, tty, no_create, command, .. } = cli.command else {
+            panic!("Expected exec")
+        };
+
+        assert!(env.is_empty());
+        assert!(!tty);
+        assert!(!no_create);
+        assert_eq!(command, ["grep", "-e", "foo", "--tty", "--no-create", "file"]);
+    }
+
+    #[test]
+    fn exec_accepts_flags_before_the_program() {
+        let cli = Cli::try_parse_from(["bx", "exec", "-e", "FOO", "--no-tty", "dev", "ls", "-la"]).unwrap();
+
+        let Command::Exec { env, no_tty, command, .. } = cli.command else {
+            panic!("Expected exec")
+        };
+
+        assert_eq!(env, ["FOO"]);
+        assert!(no_tty);
+        assert_eq!(command, ["ls", "-la"]);
+    }
+
+    #[test]
+    fn exec_requires_a_program() {
+        assert!(Cli::try_parse_from(["bx", "exec", "dev"]).is_err());
+    }
+
+    #[test]
+    fn ephemeral_passes_flags_after_the_program_through() {
+        let cli = Cli::try_parse_from(["bx", "ephemeral", "dev", "ls", "--tty", "--no-tty"]).unwrap();
+
+        let Command::Ephemeral { tty, no_tty, command, .. } = cli.command else {
+            panic!("Expected ephemeral")
+        };
+
+        assert!(!tty && !no_tty);
+        assert_eq!(command, ["ls", "--tty", "--no-tty"]);
     }
 }
@@ -0,0 +1,185 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::prelude::*;
+use crate::build::*;
+use crate::podman::*;
+
+/// What a build resolved its definition to, recorded next to the source (as `box.inputs`)
+/// so `bx diff` can show changes that aren't visible in the text.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BuildInputs {
+    /// The arguments of the FROM directive, as written.
+    pub from   : Vec<String>,
+    /// The digest of the base image the working container was created from.
+    pub base   : String,
+    /// The lockfile pin used in place of the base image, if there was one.
+    pub pinned : Option<String>,
+    /// The values of the variables a hermetic definition allows, or `None` for unset variables.
+    pub env    : BTreeMap<String, Option<String>>,
+}
+
+impl BuildInputs {
+    /// Collect the values of the variables a definition allows, if it's hermetic.
+    pub fn allowed_env(meta: &Metadata) -> BTreeMap<String, Option<String>> {
+        if !meta.hermetic {
+            return BTreeMap::new()
+        }
+
+        meta
+            .env
+            .iter()
+            .map(|key| (
+                key.to_owned(),
+                std::env::var_os(key).map(|v| v.to_string_lossy().into_owned())
+            ))
+            .collect()
+    }
+
+    /// Render the inputs as lines of text, for diffing.
+    fn render(&self) -> String {
+        let mut out = format!("FROM {}\n", self.from.join(" "));
+
+        out += &format!("base   {}\n", self.base);
+        out += &format!("pinned {}\n", self.pinned.as_deref().unwrap_or("-"));
+
+        for (key, value) in &self.env {
+            match value {
+                Some(value) => out += &format!("env    {key}={value}\n"),
+                None        => out += &format!("env    {key} (unset)\n"),
+            }
+        }
+
+        out
+    }
+}
+
+/// Print a unified diff between the source a definition's current image was built from
+/// and the definition as it exists on disk, followed by any change in its resolved inputs.
+pub fn diff(name: &str) -> Result<()> {
+    use colored::Colorize;
+
+    let def = Definition::find(name)?;
+
+    let current = std::fs::read_to_string(&def.path)
+        .context("Fault when reading in definition")?;
+
+    let image = Image::enumerate()?
+        .into_iter()
+        .find(|i| i.annotation("box.name") == Some(name));
+
+    let Some(image) = image else {
+        let err = eyre!("Definition {name} has not been built")
+            .suggestion(
+                format!("Run 'bx build {name}' first.")
+            );
+
+        return Err(err)
+    };
+
+    let Some(built) = image.annotation("box.source") else {
+        let err = eyre!("The image for {name} does not record its source")
+            .note("Images built by older versions of Box don't include the definition they were built from.")
+            .suggestion(
+                format!("Run 'bx build --force {name}' to rebuild it.")
+            );
+
+        return Err(err)
+    };
+
+    let id = &image.id[..image.id.len().min(12)];
+
+    let mut changed = print_diff(
+        &format!("{name} (built, {id})"),
+        &def.path.to_string_lossy(),
+        built,
+        &current
+    );
+
+    // Images built before inputs were recorded only have their source to compare.
+    let recorded = image
+        .annotation("box.inputs")
+        .and_then(|i| serde_json::from_str::<BuildInputs>(i).ok());
+
+    if let Some(recorded) = recorded {
+        let lock = crate::lock::Lockfile::load(&def.directory())?;
+
+        // The FROM arguments and base digest are only known once the definition is evaluated,
+        // so only the pin and the allowed variables can be resolved ahead of a rebuild.
+        let resolved = BuildInputs {
+            pinned : recorded
+                .from
+                .last()
+                .and_then(|r| lock.pin(r))
+                .map(str::to_owned),
+            env    : BuildInputs::allowed_env(&def.meta),
+            from   : recorded.from.clone(),
+            base   : recorded.base.clone(),
+        };
+
+        changed |= print_diff(
+            &format!("{name} inputs (built, {id})"),
+            &format!("{name} inputs (current)"),
+            &recorded.render(),
+            &resolved.render()
+        );
+    }
+
+    if !changed {
+        eprintln!(
+            "{} {}",
+            name.green().bold(),
+            "is unchanged since it was built.".bold().bright_white()
+        );
+    }
+
+    Ok(())
+}
+
+/// Print a colored unified diff between `old` and `new`, returning whether they differ.
+fn print_diff(old_label: &str, new_label: &str, old: &str, new: &str) -> bool {
+    use colored::Colorize;
+    use similar::{ChangeTag, TextDiff};
+    use similar::udiff::UnifiedDiffHunk;
+
+    let diff = TextDiff::from_lines(old, new);
+
+    if diff.ratio() == 1.0 {
+        return false
+    }
+
+    println!("{}", format!("--- {old_label}").bold());
+    println!("{}", format!("+++ {new_label}").bold());
+
+    for hunk in diff
+        .unified_diff()
+        .context_radius(3)
+        .iter_hunks()
+    {
+        // Fully qualified, as the prelude's Section trait also provides a header method.
+        let header = UnifiedDiffHunk::header(&hunk);
+
+        println!("{}", header.to_string().cyan());
+
+        for change in hunk.iter_changes() {
+            let line = format!(
+                "{}{}",
+                change.tag(),
+                change.value().trim_end_matches('\n')
+            );
+
+            match change.tag() {
+                ChangeTag::Delete => println!("{}", line.red()),
+                ChangeTag::Insert => println!("{}", line.green()),
+                ChangeTag::Equal  => println!("{line}"),
+            }
+
+            if change.missing_newline() {
+                println!("{}", "\\ No newline at end of file".bright_black());
+            }
+        }
+    }
+
+    true
+}
@@ -0,0 +1,709 @@
+use serde_json::{json, Map, Value};
+
+use crate::prelude::*;
+use crate::podman::*;
+use crate::cli::ExportFormat;
+
+/// A mount decoded from `--mount` syntax.
+#[derive(Debug, Default)]
+struct Mount {
+    kind      : String,
+    source    : Option<String>,
+    target    : String,
+    read_only : bool,
+    /// Any options that don't have a portable equivalent.
+    extra     : Vec<String>,
+}
+
+/// Runtime configuration decoded from a managed image's annotations,
+/// in a form that can be mapped onto other container formats.
+#[derive(Debug, Default)]
+struct Runtime {
+    name         : String,
+    mounts       : Vec<Mount>,
+    devices      : Vec<String>,
+    cap_add      : Vec<String>,
+    cap_drop     : Vec<String>,
+    security_opt : Vec<String>,
+    userns       : Option<String>,
+    memory       : Option<String>,
+    cpus         : Option<String>,
+    ulimits      : Vec<String>,
+    restart      : Option<String>,
+    secrets      : Vec<String>,
+    // Everything below is recovered from `box.args`.
+    privileged   : bool,
+    init         : bool,
+    network      : Option<String>,
+    ipc          : Option<String>,
+    user         : Option<String>,
+    ports        : Vec<String>,
+    env          : Vec<String>,
+    group_add    : Vec<String>,
+    /// Any configuration that could not be decoded.
+    unmapped     : Vec<String>,
+}
+
+impl Runtime {
+    fn from_image(image: &Image) -> Self {
+        let mut out = Self {
+            name: image
+                .annotation("box.name")
+                .expect("Name annotation should be set")
+                .to_owned(),
+            ..Default::default()
+        };
+
+        let mut args = vec![];
+
+        for (a, v) in image.runtime_config() {
+            let v = v.to_owned();
+
+            match a {
+                "args"         => args.push(v),
+                "mount"        => out.mounts.push(parse_mount(&v)),
+                "device"       => out.devices.push(v),
+                "cap-add"      => out.cap_add.push(v),
+                "cap-drop"     => out.cap_drop.push(v),
+                "security-opt" => out.security_opt.push(v),
+                "userns"       => out.userns = Some(v),
+                "memory"       => out.memory = Some(v),
+                "cpus"         => out.cpus = Some(v),
+                "ulimit"       => out.ulimits.push(v),
+                "restart"      => out.restart = Some(v),
+                "secret"       => out.secrets.push(v),
+                _              => out.unmapped.push(format!("--{a}={v}"))
+            }
+        }
+
+        out.decode_args(&args);
+        out
+    }
+
+    /// Decode the subset of `podman run` arguments that have portable equivalents.
+    fn decode_args(&mut self, args: &[String]) {
+        let mut args = args.iter();
+
+        while let Some(arg) = args.next() {
+            let (flag, inline) = match arg.split_once('=') {
+                Some((f, v)) if f.starts_with('-') => (f, Some(v.to_owned())),
+                _ => (arg.as_str(), None)
+            };
+
+            // Flags that take a value accept it either inline (--flag=value)
+            // or as the next argument (--flag value.)
+            let mut value = || inline
+                .clone()
+                .or_else(|| args.next().cloned());
+
+            match flag {
+                "--privileged"        => self.privileged = true,
+                "--init"              => self.init = true,
+                "--net" | "--network" => self.network = value(),
+                "--ipc"               => self.ipc = value(),
+                "-u" | "--user"       => self.user = value(),
+                "-p" | "--publish"    => self.ports.extend(value()),
+                "-e" | "--env"        => self.env.extend(value()),
+                "--group-add"         => self.group_add.extend(value()),
+                _                     => self.unmapped.push(arg.to_owned())
+            }
+        }
+    }
+}
+
+/// Translate the runtime configuration of a managed image into a Kubernetes Pod manifest
+/// or a compose service, printing the resulting YAML to standard output.
+pub fn export(id: &str, format: ExportFormat) -> Result<()> {
+    use colored::Colorize;
+
+    let image = Image::from_id(id)?;
+
+    let Some("box") = image.annotation("manager") else {
+        let err = eyre!("Image {id} is not managed by Box")
+            .suggestion("Box can only export images built from definitions.");
+
+        return Err(err)
+    };
+
+    let runtime      = Runtime::from_image(&image);
+    let mut warnings = vec![];
+
+    let document = match format {
+        ExportFormat::Kube    => render_kube(&runtime, &mut warnings),
+        ExportFormat::Compose => render_compose(&runtime, &mut warnings),
+    };
+
+    for arg in &runtime.unmapped {
+        warnings.push(
+            format!("argument {arg:?} could not be mapped")
+        );
+    }
+
+    for w in warnings {
+        eprintln!(
+            "{}{} {}",
+            "Warning".bold().yellow(),
+            ":".bold().bright_white(),
+            w.bold().bright_white()
+        )
+    }
+
+    let yaml = serde_yaml::to_string(&document)
+        .context("Fault when serializing exported configuration")?;
+
+    print!("{yaml}");
+
+    Ok(())
+}
+
+fn render_compose(rt: &Runtime, warnings: &mut Vec<String>) -> Value {
+    let mut service = Map::new();
+
+    service.insert("image".into(), json!(rt.name));
+    service.insert("container_name".into(), json!(rt.name));
+    service.insert("hostname".into(), json!(rt.name));
+
+    let mut volumes = vec![];
+
+    for m in &rt.mounts {
+        let mut volume = Map::new();
+
+        match m.kind.as_str() {
+            "bind" | "volume" => {
+                volume.insert("type".into(), json!(m.kind));
+                volume.insert("source".into(), json!(m.source));
+            },
+            "tmpfs" => {
+                volume.insert("type".into(), json!("tmpfs"));
+            },
+            kind => {
+                warnings.push(
+                    format!("{kind} mount at {} could not be mapped", m.target)
+                );
+                continue
+            }
+        }
+
+        volume.insert("target".into(), json!(m.target));
+
+        if m.read_only {
+            volume.insert("read_only".into(), json!(true));
+        }
+
+        for opt in &m.extra {
+            warnings.push(
+                format!("mount option {opt:?} (at {}) could not be mapped", m.target)
+            );
+        }
+
+        volumes.push(
+            Value::Object(volume)
+        );
+    }
+
+    let mut ulimits = Map::new();
+
+    for u in &rt.ulimits {
+        let Some((name, limits)) = u.split_once('=') else {
+            warnings.push(
+                format!("ulimit {u:?} could not be mapped")
+            );
+            continue
+        };
+
+        let limit = match limits.split_once(':') {
+            Some((soft, hard)) => json!({
+                "soft" : parse_number(soft),
+                "hard" : parse_number(hard)
+            }),
+            None => parse_number(limits)
+        };
+
+        ulimits.insert(name.to_owned(), limit);
+    }
+
+    let secrets: Vec<_> = rt
+        .secrets
+        .iter()
+        .map(|s| secret_name(s, warnings))
+        .collect();
+
+    let mut insert_list = |key: &str, list: &[String]| {
+        if !list.is_empty() {
+            service.insert(key.into(), json!(list));
+        }
+    };
+
+    insert_list("cap_add", &rt.cap_add);
+    insert_list("cap_drop", &rt.cap_drop);
+    insert_list("security_opt", &rt.security_opt);
+    insert_list("devices", &rt.devices);
+    insert_list("secrets", &secrets);
+    insert_list("ports", &rt.ports);
+    insert_list("environment", &rt.env);
+    insert_list("group_add", &rt.group_add);
+
+    let mut insert_opt = |key: &str, value: &Option<String>| {
+        if let Some(value) = value {
+            service.insert(key.into(), json!(value));
+        }
+    };
+
+    insert_opt("userns_mode", &rt.userns);
+    insert_opt("mem_limit", &rt.memory);
+    insert_opt("restart", &rt.restart);
+    insert_opt("network_mode", &rt.network);
+    insert_opt("ipc", &rt.ipc);
+    insert_opt("user", &rt.user);
+
+    if let Some(cpus) = &rt.cpus {
+        service.insert("cpus".into(), parse_number(cpus));
+    }
+
+    if !volumes.is_empty() {
+        service.insert("volumes".into(), Value::Array(volumes));
+    }
+
+    if !ulimits.is_empty() {
+        service.insert("ulimits".into(), Value::Object(ulimits));
+    }
+
+    if rt.privileged {
+        service.insert("privileged".into(), json!(true));
+    }
+
+    if rt.init {
+        service.insert("init".into(), json!(true));
+    }
+
+    let mut document = Map::new();
+
+    document.insert(
+        "services".into(),
+        json!({ &rt.name: service })
+    );
+
+    if !secrets.is_empty() {
+        // Secrets are managed by Podman, so they must already exist.
+        let secrets: Map<_, _> = secrets
+            .into_iter()
+            .map(|s| (s, json!({ "external": true })))
+            .collect();
+
+        document.insert("secrets".into(), Value::Object(secrets));
+    }
+
+    Value::Object(document)
+}
+
+fn render_kube(rt: &Runtime, warnings: &mut Vec<String>) -> Value {
+    let mut container = Map::new();
+    let mut pod       = Map::new();
+    let mut security  = Map::new();
+    let mut meta      = Map::new();
+
+    let mut volumes = vec![];
+    let mut mounts  = vec![];
+
+    container.insert("name".into(), json!(rt.name));
+    container.insert("image".into(), json!(rt.name));
+
+    pod.insert("hostname".into(), json!(rt.name));
+
+    for (i, m) in rt.mounts.iter().enumerate() {
+        let name = format!("mount-{i}");
+
+        let volume = match (m.kind.as_str(), &m.source) {
+            ("bind", Some(src)) => json!({
+                "name"     : name,
+                "hostPath" : { "path": src }
+            }),
+            ("volume", Some(src)) => json!({
+                "name"                  : name,
+                "persistentVolumeClaim" : { "claimName": src }
+            }),
+            ("tmpfs", _) => json!({
+                "name"     : name,
+                "emptyDir" : { "medium": "Memory" }
+            }),
+            (kind, _) => {
+                warnings.push(
+                    format!("{kind} mount at {} could not be mapped", m.target)
+                );
+                continue
+            }
+        };
+
+        for opt in &m.extra {
+            warnings.push(
+                format!("mount option {opt:?} (at {}) could not be mapped", m.target)
+            );
+        }
+
+        volumes.push(volume);
+        mounts.push(json!({
+            "name"      : name,
+            "mountPath" : m.target,
+            "readOnly"  : m.read_only
+        }));
+    }
+
+    // Kubernetes has no notion of devices, but Podman maps
+    // character device host paths back onto them.
+    for (i, d) in rt.devices.iter().enumerate() {
+        let name = format!("device-{i}");
+
+        let mut parts = d.split(':');
+        let host      = parts.next().unwrap_or(d);
+        let target    = parts.next().unwrap_or(host);
+
+        volumes.push(json!({
+            "name"     : name,
+            "hostPath" : { "path": host, "type": "CharDevice" }
+        }));
+        mounts.push(json!({
+            "name"      : name,
+            "mountPath" : target
+        }));
+    }
+
+    for s in &rt.secrets {
+        let secret = secret_name(s, warnings);
+        let name   = format!("secret-{}", sanitize(&secret));
+
+        volumes.push(json!({
+            "name"   : name,
+            "secret" : { "secretName": secret }
+        }));
+        mounts.push(json!({
+            "name"      : name,
+            "mountPath" : format!("/run/secrets/{secret}"),
+            "readOnly"  : true
+        }));
+    }
+
+    let mut capabilities = Map::new();
+
+    if !rt.cap_add.is_empty() {
+        capabilities.insert("add".into(), json!(rt.cap_add));
+    }
+
+    if !rt.cap_drop.is_empty() {
+        capabilities.insert("drop".into(), json!(rt.cap_drop));
+    }
+
+    if !capabilities.is_empty() {
+        security.insert("capabilities".into(), Value::Object(capabilities));
+    }
+
+    if rt.privileged {
+        security.insert("privileged".into(), json!(true));
+    }
+
+    for opt in &rt.security_opt {
+        match opt.as_str() {
+            // This is what Podman itself generates for disabled label separation.
+            "label=disable" | "label:disable" => {
+                security.insert(
+                    "seLinuxOptions".into(),
+                    json!({ "type": "spc_t" })
+                );
+            },
+            _ => warnings.push(
+                format!("security option {opt:?} could not be mapped")
+            )
+        }
+    }
+
+    if let Some(user) = &rt.user {
+        let mut parts = user.split(':');
+
+        let uid = parts.next().map(str::parse::<u32>);
+        let gid = parts.next().map(str::parse::<u32>);
+
+        match (uid, gid) {
+            (Some(Ok(uid)), None) => {
+                security.insert("runAsUser".into(), json!(uid));
+            },
+            (Some(Ok(uid)), Some(Ok(gid))) => {
+                security.insert("runAsUser".into(), json!(uid));
+                security.insert("runAsGroup".into(), json!(gid));
+            },
+            _ => warnings.push(
+                format!("user {user:?} could not be mapped (only numeric IDs are supported)")
+            )
+        }
+    }
+
+    if !security.is_empty() {
+        container.insert("securityContext".into(), Value::Object(security));
+    }
+
+    let mut limits = Map::new();
+
+    if let Some(memory) = &rt.memory {
+        limits.insert("memory".into(), json!(kube_quantity(memory)));
+    }
+
+    if let Some(cpus) = &rt.cpus {
+        limits.insert("cpu".into(), json!(cpus));
+    }
+
+    if !limits.is_empty() {
+        container.insert("resources".into(), json!({ "limits": limits }));
+    }
+
+    if !rt.env.is_empty() {
+        let mut env = vec![];
+
+        for e in &rt.env {
+            match e.split_once('=') {
+                Some((k, v)) => env.push(json!({ "name": k, "value": v })),
+                None => {
+                    // The host's value may well be a credential, so it's kept out of the manifest.
+                    env.push(json!({ "name": e, "value": format!("${{{e}}}") }));
+
+                    warnings.push(
+                        format!("environment variable {e} is forwarded from the host, so a placeholder was emitted in its place")
+                    )
+                }
+            }
+        }
+
+        container.insert("env".into(), json!(env));
+    }
+
+    if !rt.ports.is_empty() {
+        let mut ports = vec![];
+
+        for p in &rt.ports {
+            match parse_port(p) {
+                Some(port) => ports.push(port),
+                None => warnings.push(
+                    format!("port mapping {p:?} could not be mapped")
+                )
+            }
+        }
+
+        container.insert("ports".into(), json!(ports));
+    }
+
+    if !mounts.is_empty() {
+        container.insert("volumeMounts".into(), json!(mounts));
+        pod.insert("volumes".into(), json!(volumes));
+    }
+
+    if let Some(restart) = &rt.restart {
+        let policy = match restart.split(':').next() {
+            Some("always" | "unless-stopped") => Some("Always"),
+            Some("on-failure") => Some("OnFailure"),
+            Some("no") => Some("Never"),
+            _ => None
+        };
+
+        match policy {
+            Some(policy) => {
+                pod.insert("restartPolicy".into(), json!(policy));
+            },
+            None => warnings.push(
+                format!("restart policy {restart:?} could not be mapped")
+            )
+        }
+    }
+
+    match rt.network.as_deref() {
+        Some("host") => {
+            pod.insert("hostNetwork".into(), json!(true));
+        },
+        Some(other) => warnings.push(
+            format!("network mode {other:?} could not be mapped")
+        ),
+        None => ()
+    }
+
+    match rt.ipc.as_deref() {
+        Some("host") => {
+            pod.insert("hostIPC".into(), json!(true));
+        },
+        Some(other) => warnings.push(
+            format!("IPC mode {other:?} could not be mapped")
+        ),
+        None => ()
+    }
+
+    if !rt.group_add.is_empty() {
+        let groups: Vec<u32> = rt
+            .group_add
+            .iter()
+            .filter_map(|g| match g.parse() {
+                Ok(g) => Some(g),
+                Err(_) => {
+                    warnings.push(
+                        format!("group {g:?} could not be mapped (only numeric IDs are supported)")
+                    );
+                    None
+                }
+            })
+            .collect();
+
+        if !groups.is_empty() {
+            pod.insert(
+                "securityContext".into(),
+                json!({ "supplementalGroups": groups })
+            );
+        }
+    }
+
+    // Podman understands this annotation when playing the manifest back.
+    if let Some(userns) = &rt.userns {
+        meta.insert(
+            "io.podman.annotations.userns".into(),
+            json!(userns)
+        );
+    }
+
+    for u in &rt.ulimits {
+        warnings.push(
+            format!("ulimit {u:?} could not be mapped")
+        );
+    }
+
+    if rt.init {
+        warnings.push(
+            "argument \"--init\" could not be mapped".to_owned()
+        );
+    }
+
+    pod.insert("containers".into(), json!([container]));
+
+    let mut metadata = Map::new();
+
+    metadata.insert("name".into(), json!(rt.name));
+
+    if !meta.is_empty() {
+        metadata.insert("annotations".into(), Value::Object(meta));
+    }
+
+    json!({
+        "apiVersion" : "v1",
+        "kind"       : "Pod",
+        "metadata"   : metadata,
+        "spec"       : pod
+    })
+}
+
+/// Decode a `--mount` specification (`type=bind,src=...,dst=...`.)
+fn parse_mount(spec: &str) -> Mount {
+    let mut out = Mount {
+        kind: "volume".to_owned(),
+        ..Default::default()
+    };
+
+    for opt in spec.split(',') {
+        let (key, value) = match opt.split_once('=') {
+            Some((k, v)) => (k, Some(v)),
+            None => (opt, None)
+        };
+
+        match (key, value) {
+            ("type", Some(v)) => out.kind = v.to_owned(),
+            ("src" | "source", Some(v)) => out.source = Some(v.to_owned()),
+            ("dst" | "destination" | "target", Some(v)) => out.target = v.to_owned(),
+            ("ro" | "readonly", None | Some("true")) => out.read_only = true,
+            ("ro" | "readonly", Some("false")) => out.read_only = false,
+            _ => out.extra.push(opt.to_owned())
+        }
+    }
+
+    out
+}
+
+/// Decode a `-p` specification (`[[ip:]host:]container[/protocol]`) into a Kubernetes container port.
+fn parse_port(spec: &str) -> Option<Value> {
+    let (spec, protocol) = match spec.split_once('/') {
+        Some((s, p)) => (s, p.to_uppercase()),
+        None => (spec, "TCP".to_owned())
+    };
+
+    let mut parts = spec.rsplitn(3, ':');
+
+    let container = parts.next()?.parse::<u16>().ok()?;
+    let host      = parts.next();
+    let ip        = parts.next();
+
+    let mut port = json!({
+        "containerPort" : container,
+        "protocol"      : protocol
+    });
+
+    if let Some(host) = host {
+        port["hostPort"] = json!(host.parse::<u16>().ok()?);
+    }
+
+    if let Some(ip) = ip {
+        port["hostIP"] = json!(ip);
+    }
+
+    Some(port)
+}
+
+/// Extract the name from a `--secret` specification, warning about any options.
+fn secret_name(spec: &str, warnings: &mut Vec<String>) -> String {
+    let mut parts = spec.split(',');
+
+    let name = parts
+        .next()
+        .unwrap_or(spec)
+        .to_owned();
+
+    for opt in parts {
+        warnings.push(
+            format!("secret option {opt:?} (for {name}) could not be mapped")
+        );
+    }
+
+    name
+}
+
+/// Convert a Podman memory limit (`512m`, `2g`...) into a Kubernetes quantity.
+fn kube_quantity(memory: &str) -> String {
+    let lower = memory.to_lowercase();
+
+    // Podman accepts both 'm' and 'mb' (and so on), but a bare 'm' means milli-units to Kubernetes.
+    let (n, suffix) = match lower.strip_suffix('b') {
+        Some(n) if n.ends_with(['k', 'm', 'g']) => n.split_at(n.len() - 1),
+        Some(n) => return n.to_owned(),
+        None => lower.split_at(lower.len() - lower.ends_with(['k', 'm', 'g']) as usize),
+    };
+
+    let unit = match suffix {
+        "k" => "Ki",
+        "m" => "Mi",
+        "g" => "Gi",
+        _ => ""
+    };
+
+    format!("{n}{unit}")
+}
+
+/// Parse a number if possible, so it is emitted unquoted.
+fn parse_number(value: &str) -> Value {
+    if let Ok(n) = value.parse::<i64>() {
+        json!(n)
+    }
+    else if let Ok(n) = value.parse::<f64>() {
+        json!(n)
+    }
+    else {
+        json!(value)
+    }
+}
+
+/// Make a string usable as a Kubernetes resource name.
+fn sanitize(name: &str) -> String {
+    name
+        .to_lowercase()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect()
+}
@@ -0,0 +1,331 @@
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Deserialize;
+
+use crate::prelude::*;
+use crate::podman::*;
+use crate::CommandExt;
+
+/// The prefix of the repositories used to keep previous versions of images.
+pub const REPOSITORY: &str = "localhost/box-history/";
+
+/// The number of versions kept per definition, unless overridden by `$BOX_HISTORY`.
+const DEFAULT_LIMIT: usize = 5;
+
+/// A previous build of a definition.
+#[derive(Debug)]
+pub struct Version {
+    /// The full reference of the version's history tag.
+    pub tag       : String,
+    /// The tree hash of the definition when it was built.
+    pub tree      : String,
+    /// When the version was built, in seconds since the Unix epoch.
+    pub timestamp : u64,
+    /// The ID of the image.
+    pub image     : String,
+}
+
+/// Determines the history repository for a definition.
+pub fn repository(name: &str) -> String {
+    // Image references must be lowercase.
+    format!(
+        "{REPOSITORY}{}",
+        name.to_lowercase()
+    )
+}
+
+/// Determine how many versions to keep per definition from `$BOX_HISTORY`.
+fn limit() -> Result<usize> {
+    match std::env::var("BOX_HISTORY") {
+        Err(_) => Ok(DEFAULT_LIMIT),
+        Ok(v) => v.parse().map_err(|_| {
+            eyre!("Invalid history limit {v}")
+                .note("BOX_HISTORY must be a non-negative number.")
+                .suggestion("Set it to 0 to disable version history.")
+        })
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Enumerate the versions of a definition, newest first.
+pub fn versions(name: &str) -> Result<Vec<Version>> {
+    #[derive(Debug, Deserialize)]
+    struct Listing {
+        #[serde(rename = "Id")]
+        id    : String,
+        #[serde(rename = "Names", default)]
+        names : Vec<String>,
+    }
+
+    let repository = repository(name);
+
+    let listing: Vec<Listing> = serde_json::from_str(
+        &Command::new("podman")
+            .args([
+                "image",
+                "ls",
+                "--format",
+                "json",
+                "--filter"
+            ])
+            .arg(format!("reference={repository}"))
+            .output_ok()
+            .context("Failed to enumerate image history")?
+    )
+    .context("Failed to deserialize image listing")?;
+
+    let mut out: Vec<_> = listing
+        .iter()
+        .flat_map(|i| i.names.iter().map(move |n| (i, n)))
+        .filter_map(|(i, n)| {
+            let tag = n.strip_prefix(&repository)?.strip_prefix(':')?;
+            let (tree, timestamp) = tag.rsplit_once('-')?;
+
+            Some(Version {
+                tag       : n.to_owned(),
+                tree      : tree.to_owned(),
+                timestamp : timestamp.parse().ok()?,
+                image     : i.id.to_owned(),
+            })
+        })
+        .collect();
+
+    out.sort_by_key(|v| std::cmp::Reverse(v.timestamp));
+
+    Ok(out)
+}
+
+/// Tag a freshly built image as a new version of its definition,
+/// then remove any versions beyond the history limit.
+pub fn record(name: &str, tree: &str, image: &str) -> Result<()> {
+    let limit = limit()?;
+
+    if limit == 0 {
+        return Ok(())
+    }
+
+    let tag = format!(
+        "{}:{tree}-{}",
+        repository(name),
+        now()
+    );
+
+    debug!("Tagging {image} as {tag}");
+
+    Command::new("podman")
+        .args([
+            "tag",
+            image,
+            &tag
+        ])
+        .output_ok()
+        .context("Fault when tagging image version")?;
+
+    for version in versions(name)?.iter().skip(limit) {
+        debug!("Removing old version {}", version.tag);
+
+        let result = Command::new("podman")
+            .args([
+                "rmi",
+                &version.tag
+            ])
+            .output_ok();
+
+        if let Err(err) = result {
+            warn!("Failed to remove old version {}: {err}", version.tag);
+        }
+    }
+
+    invalidate_cache();
+
+    Ok(())
+}
+
+/// Get the ID of the image currently used for a definition's containers, if there is one.
+fn current(name: &str) -> Option<Image> {
+    Image::from_id(name).ok()
+}
+
+/// List the versions of a definition.
+pub fn history(name: &str) -> Result<()> {
+    use comfy_table::{Attribute, Cell, Color, Table};
+    use comfy_table::presets::NOTHING;
+
+    let versions = versions(name)?;
+
+    if versions.is_empty() {
+        let err = eyre!("No versions of {name} have been recorded")
+            .note("Box starts recording versions the next time a definition is built.")
+            .suggestion(
+                format!("Run 'bx build {name}' first, and make sure BOX_HISTORY isn't set to 0.")
+            );
+
+        return Err(err)
+    }
+
+    let current = current(name).map(|i| i.id);
+
+    let rows = versions
+        .iter()
+        .enumerate()
+        .map(|(idx, v)| {
+            let is_current = current.as_deref() == Some(&v.image);
+
+            let version = Cell::new(idx + 1);
+
+            let version = match is_current {
+                true  => version.fg(Color::Green).add_attribute(Attribute::Bold),
+                false => version
+            };
+
+            vec![
+                version,
+                Cell::new(&v.tree),
+                Cell::new(format_age(now().saturating_sub(v.timestamp))),
+                Cell::new(&v.image[..v.image.len().min(12)]),
+                Cell::new(if is_current { "current" } else { "" }).fg(Color::Green),
+            ]
+        });
+
+    let mut table = Table::new();
+
+    table
+        .load_preset(NOTHING)
+        .set_header(["Version", "Tree", "Built", "Image", ""])
+        .add_rows(rows);
+
+    println!("{table}");
+
+    Ok(())
+}
+
+/// Make an older version of a definition current again.
+///
+/// If `version` isn't provided, the newest version older than the current image is used.
+/// Containers using the definition are re-created if `reup` is set.
+pub fn rollback(name: &str, version: Option<usize>, reup: bool) -> Result<()> {
+    use colored::Colorize;
+
+    let versions = versions(name)?;
+    let current  = current(name);
+
+    let current_id = current
+        .as_ref()
+        .map(|i| i.id.as_str());
+
+    let target = match version {
+        Some(v) => versions
+            .get(v.wrapping_sub(1))
+            .with_context(|| format!("Version {v} of {name} does not exist"))
+            .suggestion(
+                format!("Run 'bx history {name}' to see the available versions.")
+            )?,
+        None => {
+            // Skip past the current version (and anything newer than it.)
+            let position = versions
+                .iter()
+                .position(|v| Some(v.image.as_str()) == current_id);
+
+            versions
+                .iter()
+                .skip(position.map_or(0, |p| p + 1))
+                .find(|v| Some(v.image.as_str()) != current_id)
+                .with_context(|| format!("No older versions of {name} to roll back to"))
+                .suggestion(
+                    format!("Run 'bx history {name}' to see the available versions.")
+                )?
+        }
+    };
+
+    if Some(target.image.as_str()) == current_id {
+        eprintln!(
+            "{} {}",
+            "Already using version".bold().bright_white(),
+            target.tree.green().bold()
+        );
+
+        return Ok(())
+    }
+
+    // Re-use the tags of the current image, so anything referring to them picks up the old version.
+    let mut tags: Vec<_> = current
+        .iter()
+        .flat_map(|i| &i.tags)
+        .filter(|t| !t.starts_with(REPOSITORY))
+        .cloned()
+        .collect();
+
+    if tags.is_empty() {
+        tags.push(name.to_owned());
+    }
+
+    Command::new("podman")
+        .arg("tag")
+        .arg(&target.image)
+        .args(&tags)
+        .output_ok()
+        .context("Fault when tagging image version")?;
+
+    invalidate_cache();
+
+    eprintln!(
+        "{} {} {} {}",
+        "Rolled back".bold().bright_white(),
+        name.green().bold(),
+        "to version".bold().bright_white(),
+        format!(
+            "{} ({}, built {})",
+            versions.iter().position(|v| v.tag == target.tag).unwrap_or_default() + 1,
+            target.tree,
+            format_age(now().saturating_sub(target.timestamp))
+        ).yellow().bold()
+    );
+
+    eprintln!(
+        "{}",
+        format!("The next 'bx build {name}' will rebuild it from the current definition.").bright_black()
+    );
+
+    let containers: Vec<_> = Container::enumerate()?
+        .into_iter()
+        .filter(|c| c.annotation("box.name") == Some(name))
+        .collect();
+
+    if containers.is_empty() {
+        return Ok(())
+    }
+
+    if !reup {
+        eprintln!(
+            "Run {} to re-create its container.",
+            format!("bx reup {name}").yellow()
+        );
+
+        return Ok(())
+    }
+
+    for ctr in &containers {
+        ctr.down()?;
+    }
+
+    Image::from_id(name)?.instantiate(true)?;
+
+    Ok(())
+}
+
+/// Format an age in seconds as a human-readable string.
+fn format_age(secs: u64) -> String {
+    match secs {
+        0..60         => "just now".to_owned(),
+        60..3600      => format!("{} minute(s) ago", secs / 60),
+        3600..86400   => format!("{} hour(s) ago", secs / 3600),
+        _             => format!("{} day(s) ago", secs / 86400),
+    }
+}
@@ -0,0 +1,280 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use serde::Serialize;
+
+use crate::prelude::*;
+use crate::build::*;
+use crate::podman::*;
+use crate::prune::format_size;
+
+/// Everything Box knows about a definition.
+#[derive(Debug, Serialize)]
+struct Inspection {
+    name       : String,
+    path       : PathBuf,
+    kind       : &'static str,
+    hash       : String,
+    /// Hash of the definition's unmodified text, which differs from `hash` in normalized mode.
+    raw        : String,
+    depends_on : Vec<String>,
+    /// Definitions that depend on this one.
+    dependents : Vec<String>,
+    /// The current image built from the definition, if there is one.
+    image      : Option<ImageInfo>,
+    containers : Vec<ContainerInfo>,
+}
+
+#[derive(Debug, Serialize)]
+struct ImageInfo {
+    id         : String,
+    tags       : Vec<String>,
+    size       : u64,
+    created    : Option<String>,
+    /// The hashes the image was built with.
+    hash       : Option<String>,
+    tree       : Option<String>,
+    /// Whether the definition (or one of its dependencies) has changed since the image was built.
+    stale      : bool,
+    base       : Option<String>,
+    pinned     : Option<String>,
+    /// Runtime configuration set with `CFG` and `PRESET`, keyed by option.
+    config     : BTreeMap<&'static str, Vec<String>>,
+}
+
+#[derive(Debug, Serialize)]
+struct ContainerInfo {
+    id    : String,
+    image : String,
+    state : String,
+    /// Whether the container was instantiated from an older build of the image.
+    stale : bool,
+}
+
+impl Inspection {
+    fn collect(name: &str) -> Result<Self> {
+        let def = Definition::find(name)?;
+
+        let dependents = Definition::enumerate()?
+            .iter()
+            .filter(|d| d.depends_on().iter().any(|n| n == def.name()))
+            .map(|d| d.name().to_owned())
+            .collect();
+
+        let hash = format!("{:x}", def.hash);
+        let tree = format!("{:x}", tree_hash(&def)?);
+
+        let image = Image::enumerate()?
+            .into_iter()
+            .find(|i| i.annotation("box.name") == Some(def.name()));
+
+        let containers = Container::enumerate()?
+            .into_iter()
+            .filter(|c| c.annotation("box.name") == Some(def.name()))
+            .map(|c| ContainerInfo {
+                stale : image
+                    .as_ref()
+                    .is_some_and(|i| i.annotation("box.hash") != c.annotation("box.hash")),
+                id    : c.id,
+                image : c.image,
+                state : c.state,
+            })
+            .collect();
+
+        let image = image.map(|i| {
+            let mut config: BTreeMap<_, Vec<_>> = BTreeMap::new();
+
+            for (option, value) in i.runtime_config() {
+                config
+                    .entry(option)
+                    .or_default()
+                    .push(value.to_owned());
+            }
+
+            let annotation = |key| i.annotation(key).map(str::to_owned);
+
+            ImageInfo {
+                hash    : annotation("box.hash"),
+                tree    : annotation("box.tree"),
+                stale   : i.annotation("box.hash") != Some(&hash) || i.annotation("box.tree") != Some(&tree),
+                base    : annotation("box.base-ref"),
+                pinned  : annotation("box.pinned"),
+                config,
+                id      : i.id,
+                tags    : i.tags,
+                size    : i.size,
+                created : i.created,
+            }
+        });
+
+        Ok(Self {
+            name       : def.name().to_owned(),
+            kind       : def.description(),
+            raw        : format!("{:x}", def.raw),
+            depends_on : def.depends_on().to_vec(),
+            path       : def.path,
+            hash,
+            dependents,
+            image,
+            containers,
+        })
+    }
+
+    fn print(&self) {
+        use colored::Colorize;
+        use comfy_table::{Cell, Color, Table};
+        use comfy_table::presets::NOTHING;
+
+        let none = || "-".to_owned();
+
+        let list = |l: &[String]| match l.is_empty() {
+            true  => none(),
+            false => l.join(", ")
+        };
+
+        let mut table = Table::new();
+
+        table
+            .load_preset(NOTHING)
+            .add_row(["Path", &self.path.to_string_lossy()])
+            .add_row(["Type", self.kind])
+            .add_row(["Hash", &self.hash])
+            .add_row(["Depends On", &list(&self.depends_on)])
+            .add_row(["Dependents", &list(&self.dependents)]);
+
+        println!(
+            "{}\n{table}",
+            self.name.bold().green()
+        );
+
+        let Some(image) = &self.image else {
+            println!(
+                "\n{}",
+                format!("Not built - run 'bx build {}' to build it.", self.name).bright_black()
+            );
+
+            return
+        };
+
+        let status = match image.stale {
+            true  => Cell::new("outdated (the definition or a dependency has changed)").fg(Color::Yellow),
+            false => Cell::new("up to date").fg(Color::Green),
+        };
+
+        let mut table = Table::new();
+
+        table
+            .load_preset(NOTHING)
+            .add_row(["ID", &image.id[..image.id.len().min(12)]])
+            .add_row(["Tags", &list(&image.tags)])
+            .add_row(["Size", &format_size(image.size)])
+            .add_row(["Created", image.created.as_deref().unwrap_or("-")])
+            .add_row(["Tree", image.tree.as_deref().unwrap_or("-")])
+            .add_row(["Base", image.base.as_deref().unwrap_or("-")])
+            .add_row(vec![Cell::new("Status"), status]);
+
+        if let Some(pinned) = &image.pinned {
+            table.add_row(["Pinned", pinned]);
+        }
+
+        println!(
+            "\n{}\n{table}",
+            "Image".bold().bright_white()
+        );
+
+        if !image.config.is_empty() {
+            let mut table = Table::new();
+
+            table.load_preset(NOTHING);
+
+            for (option, values) in &image.config {
+                for (idx, value) in values.iter().enumerate() {
+                    let option = match idx {
+                        0 => *option,
+                        _ => ""
+                    };
+
+                    table.add_row([option, value]);
+                }
+            }
+
+            println!(
+                "\n{}\n{table}",
+                "Runtime Configuration".bold().bright_white()
+            );
+        }
+
+        if self.containers.is_empty() {
+            return
+        }
+
+        let rows = self
+            .containers
+            .iter()
+            .map(|c| vec![
+                Cell::new(&c.id[..c.id.len().min(12)]),
+                Cell::new(&c.image),
+                Cell::new(&c.state),
+                match c.stale {
+                    true  => Cell::new("outdated - run 'bx reup' to update").fg(Color::Yellow),
+                    false => Cell::new("")
+                }
+            ]);
+
+        let mut table = Table::new();
+
+        table
+            .load_preset(NOTHING)
+            .set_header(["ID", "Image", "Status", ""])
+            .add_rows(rows);
+
+        println!(
+            "\n{}\n{table}",
+            "Containers".bold().bright_white()
+        );
+    }
+}
+
+/// Compute the tree hash of a definition the same way builds do, by folding in the hash of
+/// every definition it (transitively) depends on.
+fn tree_hash(def: &Definition) -> Result<u64> {
+    let mut tree    = def.hash;
+    let mut seen    = vec![def.name().to_owned()];
+    let mut pending = def.depends_on().to_vec();
+
+    while let Some(name) = pending.pop() {
+        if seen.contains(&name) {
+            continue
+        }
+
+        let dep = Definition::find(&name)
+            .context("Fault when searching for definition dependency")?;
+
+        if tree != dep.hash {
+            tree ^= dep.hash;
+        }
+
+        pending.extend_from_slice(dep.depends_on());
+        seen.push(name);
+    }
+
+    Ok(tree)
+}
+
+/// Show a definition along with its image, runtime configuration and containers.
+pub fn inspect(name: &str, json: bool) -> Result<()> {
+    let inspection = Inspection::collect(name)?;
+
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&inspection)
+                .context("Fault when serializing inspection")?
+        );
+    }
+    else {
+        inspection.print();
+    }
+
+    Ok(())
+}
@@ -0,0 +1,247 @@
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use serde::{Deserialize, Serialize};
+
+use crate::prelude::*;
+use crate::build::*;
+use crate::CommandExt;
+
+/// The name of the lock file, which lives next to the definitions it applies to.
+pub const LOCK_FILE: &str = "box.lock";
+
+/// Pins the base images referenced by FROM directives to specific digests.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Lockfile {
+    /// Maps each reference (as written in a definition) to a digest-pinned reference.
+    #[serde(default)]
+    pub images: BTreeMap<String, String>,
+}
+
+impl Lockfile {
+    /// Load the lock file in the provided directory, returning an empty one if it doesn't exist.
+    pub fn load(dir: &Path) -> Result<Self> {
+        let path = dir.join(LOCK_FILE);
+
+        if !path.exists() {
+            return Ok(Self::default())
+        }
+
+        let data = std::fs::read_to_string(&path)
+            .context("Fault when reading in lock file")?;
+
+        toml::from_str(&data)
+            .context("Failed to deserialize lock file")
+            .suggestion(
+                format!("Try deleting {} and running 'bx lock' again.", path.to_string_lossy())
+            )
+    }
+
+    /// Write the lock file to the provided directory.
+    pub fn write(&self, dir: &Path) -> Result<PathBuf> {
+        let path = dir.join(LOCK_FILE);
+
+        let data = toml::to_string_pretty(self)
+            .context("Failed to serialize lock file")?;
+
+        std::fs::write(
+            &path,
+            format!("# Generated by 'bx lock'; use 'bx lock --update' to bump pinned images.\n{data}")
+        )
+        .context("Fault when writing lock file")?;
+
+        Ok(path)
+    }
+
+    /// Get the pinned reference for an image, if there is one.
+    pub fn pin(&self, reference: &str) -> Option<&str> {
+        self
+            .images
+            .get(reference)
+            .map(String::as_str)
+    }
+}
+
+/// Extract the image references used by FROM directives in the provided definition text.
+///
+/// This is a line-based heuristic; references are taken to be the final argument of a FROM,
+/// and those that can't be resolved ahead of time (variables, other definitions, already-pinned
+/// images...) are omitted.
+pub fn base_images(script: &str) -> Vec<String> {
+    let mut out = vec![];
+
+    for line in script.lines() {
+        let Some(rest) = line.trim_start().strip_prefix("FROM") else {
+            continue
+        };
+
+        if !rest.starts_with(|c: char| c.is_whitespace() || c == '(') {
+            continue
+        }
+
+        // Covers both the shell and Rhai forms, e.g. FROM(["--pull=newer", "fedora"]);
+        let rest = rest.replace(['(', ')', '[', ']', ';', ',', '"', '\''], " ");
+
+        let Some(reference) = rest.split_whitespace().last() else {
+            continue
+        };
+
+        let dynamic = reference.contains(['$', '{', '`']);
+        let pinned  = reference.contains('@');
+        let local   = reference.starts_with("localhost/");
+
+        if dynamic || pinned || local || reference.starts_with('-') {
+            continue
+        }
+
+        if !out.iter().any(|r| r == reference) {
+            out.push(reference.to_owned())
+        }
+    }
+
+    out
+}
+
+/// Pull an image and determine its digest-pinned reference.
+pub fn resolve(reference: &str) -> Result<String> {
+    #[derive(Debug, Deserialize)]
+    struct Inspect {
+        #[serde(rename = "Digest")]
+        digest: String,
+        #[serde(rename = "RepoDigests", default)]
+        repo_digests: Vec<String>,
+    }
+
+    let id = Command::new("podman")
+        .args([
+            "pull",
+            "--quiet",
+            reference
+        ])
+        .output_ok()
+        .context(
+            format!("Fault when pulling image {reference}")
+        )?;
+
+    let raw_json = Command::new("podman")
+        .args([
+            "image",
+            "inspect",
+            "--format",
+            "json",
+            id.trim()
+        ])
+        .output_ok()
+        .context("Failed to inspect image JSON")?;
+
+    let [image]: [Inspect; 1] = serde_json::from_str(&raw_json)
+        .context("Failed to deserialize image JSON")?;
+
+    // An image can be known by several repositories, so prefer the digest of the exact manifest that was pulled.
+    image
+        .repo_digests
+        .iter()
+        .find(|r| r.ends_with(&format!("@{}", image.digest)))
+        .or(image.repo_digests.first())
+        .cloned()
+        .with_context(|| format!("Image {reference} has no repository digest"))
+}
+
+/// Resolve the base images of the provided definitions (or all definitions, if none are provided)
+/// and record them in the lock file.
+///
+/// Images that are already pinned are left alone unless `update` is set.
+pub fn lock_set(defs: &[String], update: bool) -> Result<()> {
+    use colored::Colorize;
+
+    let dir  = definition_directory()?;
+    let all  = Definition::enumerate()?;
+    let mut lock = Lockfile::load(&dir)?;
+
+    let targets = match defs.is_empty() {
+        true  => Definition::enumerate()?,
+        false => defs
+            .iter()
+            .map(|d| Definition::find(d))
+            .collect::<Result<Vec<_>>>()?
+    };
+
+    let names: HashSet<_> = all
+        .iter()
+        .map(|d| d.name())
+        .collect();
+
+    let references = |def: &Definition| -> Result<Vec<String>> {
+        let script = std::fs::read_to_string(&def.path)
+            .context("Fault when reading in definition")?;
+
+        let out = base_images(&script)
+            .into_iter()
+            .filter(|r| !names.contains(r.as_str()))
+            .collect();
+
+        Ok(out)
+    };
+
+    // Locking everything also drops pins that are no longer referenced.
+    if defs.is_empty() {
+        let mut referenced = HashSet::new();
+
+        for def in &all {
+            referenced.extend(references(def)?);
+        }
+
+        lock.images.retain(|r, _| {
+            let keep = referenced.contains(r);
+
+            if !keep {
+                eprintln!(
+                    "{} {}",
+                    "Unpinned".bold().bright_white(),
+                    r.yellow().bold()
+                );
+            }
+
+            keep
+        });
+    }
+
+    let mut resolved = HashSet::new();
+
+    for def in &targets {
+        for reference in references(def)? {
+            if resolved.contains(&reference) || (!update && lock.pin(&reference).is_some()) {
+                continue
+            }
+
+            let pinned = resolve(&reference)?;
+
+            let status = match lock.pin(&reference) {
+                Some(old) if old == pinned => "Unchanged",
+                Some(_) => "Updated",
+                None    => "Pinned",
+            };
+
+            eprintln!(
+                "{} {} {}",
+                status.bold().bright_white(),
+                reference.green().bold(),
+                format!("-> {pinned}").bright_black()
+            );
+
+            lock.images.insert(reference.clone(), pinned);
+            resolved.insert(reference);
+        }
+    }
+
+    let path = lock.write(&dir)?;
+
+    eprintln!(
+        "{} {}",
+        "Wrote lock file".bold().bright_white(),
+        path.to_string_lossy().green().bold()
+    );
+
+    Ok(())
+}
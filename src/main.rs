@@ -1,6 +1,17 @@
+mod api;
 mod build;
 mod cli;
+mod diff;
+mod export;
+mod history;
+mod inspect;
+mod lock;
+mod outdated;
+mod prune;
 mod podman;
+mod script;
+mod systemd;
+mod template;
 
 mod prelude {
     pub use color_eyre::eyre::{
@@ -25,17 +36,45 @@ mod prelude {
     };
 }
 
+use std::ffi::OsString;
+use std::process::ExitCode;
+
 use prelude::*;
 use build::*;
 use cli::*;
+use diff::*;
+use export::*;
+use history::*;
+use inspect::*;
+use lock::*;
+use outdated::*;
+use prune::*;
 use podman::*;
+use systemd::*;
+use template::*;
 
 #[cfg(not(target_os = "linux"))]
 compile_error!(
     "Box targets Linux only - compilation halted."
 );
 
-fn main() -> Result<()> {
+/// The exit code used when Box itself fails, matching Podman's convention.
+/// 
+/// This keeps Box's failures distinguishable from those of commands run with `exec`,
+/// `enter` and `ephemeral`, whose exit codes are passed through.
+const FAILURE: u8 = 125;
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(code) => code,
+        Err(err) => {
+            eprintln!("Error: {err:?}");
+            ExitCode::from(FAILURE)
+        }
+    }
+}
+
+fn run() -> Result<ExitCode> {
     use clap::Parser;
     use indicatif::{ProgressBar, ProgressStyle};
     use Command::*;
@@ -142,12 +181,14 @@ fn main() -> Result<()> {
             )
         },
 
-        Create { name } => Definition::create(name)?,
+        Create { name, template, interactive } => Definition::create(name, template, interactive)?,
         Edit   { name } => Definition::edit(name)?,
         Delete { name, yes } => Definition::delete(name, yes)?,
+        Rename { old, new } => Definition::rename(old, new)?,
+        Clone  { src, dst } => Definition::clone(src, dst)?,
 
-        Enter { name } => {
-            existence_check(&name)?;
+        Enter { name, no_create, env } => {
+            offer_creation(&name, no_create)?;
 
             let ctr = Container::from_id(&name)?;
 
@@ -155,10 +196,10 @@ fn main() -> Result<()> {
                 ctr.start()?;
             }
 
-            ctr.enter()?;
+            return ctr.enter(&env)
         },
-        Exec { name, path, args } => {
-            existence_check(&name)?;
+        Exec { name, no_create, env, tty, no_tty, command } => {
+            offer_creation(&name, no_create)?;
 
             let ctr = Container::from_id(&name)?;
 
@@ -166,20 +207,49 @@ fn main() -> Result<()> {
                 ctr.start()?;
             }
 
-            ctr.exec(&path, &args)?;
+            // Clap guarantees at least the program is present.
+            let (path, args) = command
+                .split_first()
+                .expect("Command should not be empty");
+
+            return ctr.exec(path, args, use_tty(tty, no_tty), &env)
         },
-        Ephemeral { name, path, mut args } => {
+        Ephemeral { name, tty, no_tty, command } => {
             let image = Image::from_id(&name)?;
 
-            args.insert(0, path);
-
-            image.instantiate_ext(
+            return image.instantiate_ext(
                 false,
-                &args
-            )?;
+                &command,
+                use_tty(tty, no_tty)
+            )
         },
 
-        Build { defs, all, force } => build_set(&defs, all, force)?,
+        Build { defs, all, force, no_cache, report } => {
+            let report = match report.as_deref() {
+                Some([format, file]) if format == "json" => Some(
+                    std::path::PathBuf::from(file)
+                ),
+                Some([format, _]) => {
+                    let err = eyre!("Unsupported report format {format}")
+                        .suggestion("Box can only write reports as json.");
+
+                    return Err(err)
+                },
+                _ => None
+            };
+
+            build_set(&defs, all, force, no_cache, report.as_deref())?
+        },
+        Lock    { defs, update } => lock_set(&defs, update)?,
+        Outdated => outdated()?,
+        Diff     { name } => diff(&name)?,
+        Inspect  { name, json } => inspect(&name, json)?,
+        History  { name } => history(&name)?,
+        Rollback { name, version, reup } => rollback(&name, version, reup)?,
+        Prune { yes } => prune(yes)?,
+        Templates => list_templates()?,
+        Systemd { name, stdout, replace } => generate_unit(&name, stdout, replace)?,
+        Export  { name, format } => export(&name, format)?,
 
         Start   (set) => map_set(&set, Container::start, "Starting")?,
         Stop    (set) => map_set(&set, Container::stop, "Stopping")?,
@@ -224,25 +294,22 @@ fn main() -> Result<()> {
             instantiate(&set, replace)?;
         }
 
-        Init { shell } => match &*shell {
-            "fish"  => {
-                print!(
-                    "{}",
-                    include_str!("shell/fish.sh")
-                )
-            },
-            "posix" => {
-                print!(
-                    "{}",
-                    include_str!("shell/posix.sh")
-                )
-            },
-            _       => unreachable!()
+        Init { shell } => {
+            print!(
+                "{}",
+                Shell::from_name(&shell)?.harness()
+            )
         },
         Config { operation, args } => evaluate_config(operation, args)?,
+
+        From   { args } => evaluate_from(&args)?,
+        Run    { args } => evaluate_config("run".to_owned(), args)?,
+        Add    { args } => evaluate_config("add".to_owned(), args)?,
+        Commit { args } => evaluate_config("commit".to_owned(), args)?,
+        Cfg    { operation, args } => evaluate_config(operation, args)?,
     }
 
-    Ok(())
+    Ok(ExitCode::SUCCESS)
 }
 
 fn install_logging() {
@@ -256,6 +323,82 @@ fn install_logging() {
         .expect("Could not install Eyre hooks!");
 }
 
+/// Determine whether or not to allocate a pseudo-TTY for a command, given the `--tty` and `--no-tty` flags.
+/// 
+/// Without either flag, one is only allocated when both standard input and output are terminals.
+fn use_tty(tty: bool, no_tty: bool) -> bool {
+    use std::io::IsTerminal;
+
+    match (tty, no_tty) {
+        (true, _) => true,
+        (_, true) => false,
+        _ => std::io::stdin().is_terminal() && std::io::stdout().is_terminal()
+    }
+}
+
+/// Ensures a container exists before entering or executing inside it.
+/// 
+/// If it doesn't, the user is offered to create it from the matching managed image, building
+/// its definition first if needed. This is skipped (falling back to [`existence_check`]) when `strict`
+/// is set or Box isn't running interactively.
+fn offer_creation(name: &str, strict: bool) -> Result<()> {
+    use std::io::IsTerminal;
+
+    use colored::Colorize;
+    use dialoguer::Confirm;
+
+    let interactive = std::io::stdin().is_terminal() && std::io::stderr().is_terminal();
+
+    if strict || !interactive || Container::exists(name)? {
+        return existence_check(name)
+    }
+
+    let find_image = || -> Result<_> {
+        let image = Image::enumerate()?
+            .into_iter()
+            .find(|i| i.annotation("box.name") == Some(name));
+
+        Ok(image)
+    };
+
+    let image = find_image()?;
+
+    let prompt = match (&image, Definition::exists(name)?) {
+        (Some(_), _) => format!("Container {name} does not exist - create it from its image?"),
+        (None, true) => format!("Container {name} does not exist and has not been built - build and create it?"),
+        (None, false) => return existence_check(name),
+    };
+
+    let confirm = Confirm::new()
+        .with_prompt(prompt)
+        .default(true)
+        .interact()
+        .context("Fault when asking for user confirmation")?;
+
+    if !confirm {
+        return existence_check(name)
+    }
+
+    let image = match image {
+        Some(image) => image,
+        None => {
+            build_set(&[name.to_owned()], false, false, false, None)?;
+
+            find_image()?
+                .with_context(|| format!("Building {name} did not produce an image"))
+                .suggestion("Does its COMMIT directive use the definition's name?")?
+        }
+    };
+
+    eprintln!(
+        "{} {}",
+        "Creating".bold().bright_white(),
+        name.green().bold()
+    );
+
+    image.instantiate(false)
+}
+
 /// Checks if a container exists, returning a well-formed error (with fuzzy-matched suggestions) if not.
 fn existence_check(id: &str) -> Result<()> {
     use nucleo_matcher::{Matcher, Config};
@@ -363,12 +506,7 @@ fn list_definitions() -> Result<()> {
         .iter()
         .map(|d| [
             d.name(),
-            if d.bang.contains("fish") {
-                "Fish script"
-            }
-            else {
-                "POSIX script"
-            }
+            d.description()
         ]);
     
     table
@@ -381,17 +519,332 @@ fn list_definitions() -> Result<()> {
     Ok(())
 }
 
-fn evaluate_config(operation: String, args: Vec<String>) -> Result<()> {
+/// Determine the working container for the current build.
+/// 
+/// Harnessed definitions export it as `__BOX_BUILD_CTR`; definitions without a harness
+/// can't do that across processes, so it's read back from the build state file instead.
+fn build_container() -> Result<String> {
+    if let Ok(ctr) = std::env::var("__BOX_BUILD_CTR") {
+        return Ok(ctr)
+    }
+
+    let ctr = std::env::var("__BOX_BUILD_STATE")
+        .ok()
+        .and_then(|path| std::fs::read_to_string(path).ok());
+
+    match ctr {
+        Some(ctr) => Ok(
+            ctr.trim().to_owned()
+        ),
+        None => {
+            let err = eyre!("Config command must be invoked inside of a build context")
+                .suggestion("This is probably happening due to an issue with a FROM directive.")
+                .suggestion("Alternately, it may be a bug in Box.");
+
+            Err(err)
+        }
+    }
+}
+
+/// Evaluates a FROM directive from the environment's build context.
+/// 
+/// The name of the working container is printed to standard output.
+fn evaluate_from(args: &[String]) -> Result<()> {
+    let context = BuildContext::from_env()?;
+    let ctr     = create_container(args, &context)?;
+
+    if let Ok(state) = std::env::var("__BOX_BUILD_STATE") {
+        std::fs::write(state, &ctr)
+            .context("Fault when writing build state")?;
+    }
+
+    println!("{ctr}");
+
+    Ok(())
+}
+
+/// Creates a new working container and applies the annotations Box needs
+/// to manage the resulting image, returning the container's name.
+fn create_container(args: &[String], context: &BuildContext) -> Result<String> {
+    use std::process::{Command, Stdio};
+
+    if args.is_empty() {
+        let err = eyre!("Base image not specified")
+            .suggestion("FROM directives need at least an image name.");
+
+        return Err(err)
+    }
+
+    let lock = Lockfile::load(
+        context.path.parent().unwrap_or(&context.path)
+    )?;
+
+    // The base image is always the final argument.
+    let pinned = args
+        .last()
+        .and_then(|r| lock.pin(r))
+        .map(str::to_owned);
+
+    let mut from = args.to_vec();
+
+    if let Some(pinned) = &pinned {
+        info!("Using pinned base image {pinned}");
+
+        *from.last_mut().unwrap() = pinned.to_owned();
+    }
+
+    // Kept in the image so 'bx diff' can show what a rebuild would change.
+    let source = std::fs::read_to_string(&context.path)
+        .context("Fault when reading in definition source")?;
+
+    // Standard error is passed through so image pull progress remains visible.
+    let ctr = Command::new("buildah")
+        .arg("from")
+        .args(&from)
+        .stderr(Stdio::inherit())
+        .output_ok()
+        .context("Fault when creating working container")?
+        .trim()
+        .to_owned();
+
+    // Recorded so 'bx outdated' can tell when the base image has moved on.
+    let base = Command::new("buildah")
+        .args([
+            "inspect",
+            "--type",
+            "container",
+            "--format",
+            "{{.FromImageDigest}}",
+            &ctr
+        ])
+        .output_ok()
+        .context("Fault when inspecting working container")?
+        .trim()
+        .to_owned();
+
+    let meta = Metadata::parse(
+        &source,
+        Kind::detect(&context.path, source.lines().next().unwrap_or_default())
+    )?;
+
+    let inputs = BuildInputs {
+        from   : args.to_vec(),
+        base   : base.to_owned(),
+        pinned : pinned.to_owned(),
+        env    : BuildInputs::allowed_env(&meta),
+    };
+
+    let inputs = serde_json::to_string(&inputs)
+        .context("Fault when serializing build inputs")?;
+
+    Command::new("buildah")
+        .arg("config")
+        .args([
+            "-a",
+            "manager=box",
+            "-a",
+            &format!("box.path={}", context.path.to_string_lossy()),
+            "-a",
+            &format!("box.hash={}", context.hash),
+            "-a",
+            &format!("box.tree={}", context.tree),
+            "-a",
+            &format!("box.name={}", context.name),
+            "-a",
+            &format!("box.from={}", args.join("\x1F")),
+            "-a",
+            &format!("box.base={base}"),
+            "-a",
+            &format!("box.base-ref={}", args[args.len() - 1]),
+            "-a",
+            &format!("box.source={source}"),
+            "-a",
+            &format!("box.inputs={inputs}"),
+        ])
+        .arg(&ctr)
+        .spawn_ok()
+        .context("Fault when writing annotations to working container")?;
+
+    if let Some(pinned) = pinned {
+        Command::new("buildah")
+            .arg("config")
+            .args([
+                "-a",
+                &format!("box.pinned={pinned}")
+            ])
+            .arg(&ctr)
+            .spawn_ok()
+            .context("Fault when writing annotations to working container")?;
+    }
+
+    Ok(ctr)
+}
+
+/// Creates a new working container from a checkpoint image, returning the container's name.
+/// 
+/// The arguments of the original FROM directive are re-used (minus the base image and any
+/// pull policy) so the working container is configured identically.
+fn resume_container(image: &str, context: &BuildContext) -> Result<String> {
     use std::process::Command;
 
-    let Ok(ctr) = std::env::var("__BOX_BUILD_CTR") else {
-        let err = eyre!("Config command must be invoked inside of a build context")
-            .suggestion("This is probably happening due to an issue with a FROM directive.")
-            .suggestion("Alternately, it may be a bug in Box.");
+    let checkpoint = Image::from_id(image)
+        .context("Fault when inspecting checkpoint image")?;
+
+    let from = checkpoint
+        .annotation("box.from")
+        .unwrap_or_default()
+        .to_owned();
+
+    let original: Vec<String> = from
+        .split('\x1F')
+        .filter(|a| !a.is_empty())
+        .map(str::to_owned)
+        .collect();
+
+    let mut args = vec![];
+    let mut iter = original
+        .iter()
+        .take(original.len().saturating_sub(1))
+        .peekable();
+
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--pull" => {
+                let policy = iter.next_if(|p| {
+                    ["always", "missing", "never", "newer", "true", "false"].contains(&p.as_str())
+                });
+
+                debug!("Dropping pull policy {policy:?} from resumed FROM");
+            },
+            a if a.starts_with("--pull=") || a == "--pull-always" || a == "--pull-never" => (),
+            _ => args.push(arg.to_owned())
+        }
+    }
+
+    args.push(image.to_owned());
+
+    let ctr = create_container(&args, context)?;
+
+    // The checkpoint's FROM arguments, base image and recorded inputs are kept, so later checkpoints
+    // can be resumed the same way (and the checkpoint isn't mistaken for the base image.)
+    let mut c = Command::new("buildah");
+
+    c.arg("config");
+
+    for key in ["box.from", "box.base", "box.base-ref", "box.pinned", "box.inputs"] {
+        if let Some(value) = checkpoint.annotation(key) {
+            c.arg("-a").arg(format!("{key}={value}"));
+        }
+    }
+
+    c
+        .arg(&ctr)
+        .spawn_ok()
+        .context("Fault when writing annotations to working container")?;
 
-        return Err(err);
+    Ok(ctr)
+}
+
+/// Commits the working container as a checkpoint image.
+/// 
+/// Checkpoints are marked with a distinct manager annotation so they aren't
+/// mistaken for the images Box manages.
+fn commit_checkpoint(ctr: &str, image: &str) -> Result<()> {
+    use std::process::Command;
+
+    let manager = |value: &str| {
+        Command::new("buildah")
+            .arg("config")
+            .arg("-a")
+            .arg(format!("manager={value}"))
+            .arg(ctr)
+            .spawn_ok()
+            .context("Fault when writing annotations to working container")
     };
-    
+
+    debug!("Committing checkpoint {image}...");
+
+    manager("box-checkpoint")?;
+
+    let result = Command::new("buildah")
+        .args([
+            "commit",
+            "--quiet",
+            ctr,
+            image
+        ])
+        .output_ok()
+        .context("Fault when committing checkpoint");
+
+    manager("box")?;
+
+    result.map(|_| ())
+}
+
+fn evaluate_config(operation: String, args: Vec<String>) -> Result<()> {
+    // Directives before the checkpoint being resumed from are already part of its image.
+    if std::env::var_os("__BOX_BUILD_SKIP").is_some() {
+        return Ok(())
+    }
+
+    match operation.as_str() {
+        "resume" => {
+            let context = BuildContext::from_env()?;
+
+            let image = std::env::var("__BOX_BUILD_RESUME_IMAGE")
+                .context("No checkpoint to resume from")?;
+
+            let ctr = resume_container(&image, &context)?;
+
+            println!("{ctr}");
+
+            Ok(())
+        },
+        "checkpoint" => {
+            let context = BuildContext::from_env()?;
+            let ctr     = build_container()?;
+
+            let index: usize = args
+                .first()
+                .and_then(|i| i.parse().ok())
+                .filter(|i| *i > 0)
+                .context("Checkpoint index not specified")?;
+
+            let keys: Vec<_> = std::env::var("__BOX_BUILD_CHECKPOINTS")
+                .unwrap_or_default()
+                .split_whitespace()
+                .filter_map(|k| u64::from_str_radix(k, 16).ok())
+                .collect();
+
+            let Some(key) = keys.get(index - 1).copied() else {
+                // Each call is a separate process, so only warn on the first extra checkpoint.
+                if index == keys.len() + 1 {
+                    warn!(
+                        "{} called more checkpoints than it has unindented CHECKPOINT lines - ignoring the rest",
+                        context.name
+                    );
+                }
+
+                return Ok(())
+            };
+
+            commit_checkpoint(
+                &ctr,
+                &checkpoint_image(&context.name, key)
+            )
+        },
+        _ => {
+            let ctr = build_container()?;
+
+            evaluate_directive(&ctr, &operation, &args)
+        }
+    }
+}
+
+/// Evaluates a directive (RUN, ADD, CFG...) against the provided working container.
+fn evaluate_directive(ctr: &str, operation: &str, args: &[String]) -> Result<()> {
+    use std::process::Command;
+
     // Certain operations, like ADD and RUN, need to be split
     // based on the presence of an '--' arg so they can be re-arranged
     // appropriately.
@@ -407,14 +860,14 @@ fn evaluate_config(operation: String, args: Vec<String>) -> Result<()> {
             (l, r)
         }
         None => (
-            args.as_slice(),
+            args,
             [].as_slice()
         )
     };
 
     debug!("Post-processed arguments: {args:?} // {trailing:?}");
 
-    match operation.as_str() {
+    match operation {
         // We handle ADD/COPY and RUN in Rust code,
         // because correctly handling arguments split by --
         // in shell is... non trivial.
@@ -482,7 +935,17 @@ fn evaluate_config(operation: String, args: Vec<String>) -> Result<()> {
             c.spawn_ok()?
         },
         "preset" => {
-            evaluate_preset(&ctr, args)?
+            evaluate_preset(ctr, args)?
+        },
+        // Not passed to 'podman run', so it's kept separate from the other annotations.
+        "exec-env" => {
+            if args.is_empty() {
+                bail!("Variable name not specified")
+            }
+
+            for a in args {
+                push_annotation(ctr, "box.exec-env", a)?;
+            }
         },
         o if ANNOTATIONS.contains(&o) => {
             if args.is_empty() {
@@ -492,7 +955,7 @@ fn evaluate_config(operation: String, args: Vec<String>) -> Result<()> {
             // Gracefully handle the case where multiple values are provided.
             for a in args {
                 push_annotation(
-                    &ctr,
+                    ctr,
                     &format!("box.{o}"),
                     a
                 )?;
@@ -612,6 +1075,46 @@ fn evaluate_preset(ctr: &str, args: &[String]) -> Result<()> {
     Ok(())
 }
 
+thread_local! {
+    /// The environment commands spawned on this thread are restricted to, if any.
+    static ENVIRONMENT: std::cell::RefCell<Option<Vec<(OsString, OsString)>>> = const {
+        std::cell::RefCell::new(None)
+    };
+}
+
+/// Run `f` with every command spawned through [`CommandExt`] on this thread restricted to `vars`
+/// (or inheriting the full environment, if `None`.)
+/// 
+/// Used to keep in-process (Rhai) definitions hermetic without modifying the environment of
+/// the whole process.
+pub fn with_environment<T>(vars: Option<Vec<(OsString, OsString)>>, f: impl FnOnce() -> T) -> T {
+    /// Restores the previous environment, even if `f` panics.
+    struct Restore(Option<Vec<(OsString, OsString)>>);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            ENVIRONMENT.set(self.0.take());
+        }
+    }
+
+    let _restore = Restore(
+        ENVIRONMENT.replace(vars)
+    );
+
+    f()
+}
+
+/// Apply the environment set by [`with_environment`] (if any) to a command.
+fn restrict_environment(c: &mut std::process::Command) {
+    ENVIRONMENT.with_borrow(|vars| {
+        if let Some(vars) = vars {
+            c
+                .env_clear()
+                .envs(vars.iter().map(|(k, v)| (k, v)));
+        }
+    });
+}
+
 /// Boilerplate reduction extension trait. Wraps the `spawn` and `output` methods
 /// on [`Command`](std::process::Command) to automatically generate eyre-compatible errors
 /// on failure.
@@ -630,6 +1133,8 @@ pub trait CommandExt {
 
 impl CommandExt for std::process::Command {
     fn output_ok(&mut self) -> Result<String> {
+        restrict_environment(self);
+
         debug!("Shelling out; command is {self:?}");
         
         let o = self.output()?;
@@ -668,6 +1173,8 @@ impl CommandExt for std::process::Command {
     }
 
     fn spawn_ok(&mut self) -> Result<()> {
+        restrict_environment(self);
+
         debug!("Shelling out; command is {self:?}");
 
         let make_message = |c: &std::process::Command| {
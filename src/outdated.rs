@@ -0,0 +1,278 @@
+use std::collections::HashSet;
+use std::process::Command;
+
+use serde::Deserialize;
+
+use crate::prelude::*;
+use crate::build::*;
+use crate::podman::*;
+use crate::CommandExt;
+
+/// How a base image digest compares to the one a managed image was built on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    /// The digest matches.
+    Current,
+    /// The digest differs, so a newer version is available.
+    Newer,
+    /// The reference isn't present in local storage.
+    Missing,
+    /// The registry could not be queried.
+    Unreachable,
+    /// The registry was not queried (skopeo isn't installed.)
+    Unchecked,
+}
+
+impl Status {
+    fn cell(self) -> comfy_table::Cell {
+        use comfy_table::{Attribute, Cell, Color};
+
+        match self {
+            Self::Current     => Cell::new("current").fg(Color::Green),
+            Self::Newer       => Cell::new("newer").fg(Color::Yellow).add_attribute(Attribute::Bold),
+            Self::Missing     => Cell::new("missing").fg(Color::DarkGrey),
+            Self::Unreachable => Cell::new("unreachable").fg(Color::DarkGrey),
+            Self::Unchecked   => Cell::new("-").fg(Color::DarkGrey),
+        }
+    }
+}
+
+/// The local copy of a base image.
+struct LocalImage {
+    /// Every digest the image is known by (its own, plus those of its repositories.)
+    digests : HashSet<String>,
+    /// The fully-qualified name of the image, if it has one.
+    name    : Option<String>,
+}
+
+impl LocalImage {
+    /// Look up a reference in local storage.
+    fn find(reference: &str) -> Option<Self> {
+        #[derive(Debug, Deserialize)]
+        struct Inspect {
+            #[serde(rename = "Digest")]
+            digest: String,
+            #[serde(rename = "RepoDigests", default)]
+            repo_digests: Vec<String>,
+            #[serde(rename = "RepoTags", default)]
+            repo_tags: Vec<String>,
+        }
+
+        let raw_json = Command::new("podman")
+            .args([
+                "image",
+                "inspect",
+                "--format",
+                "json",
+                reference
+            ])
+            .output_ok()
+            .ok()?;
+
+        let [image]: [Inspect; 1] = serde_json::from_str(&raw_json).ok()?;
+
+        let mut digests: HashSet<_> = image
+            .repo_digests
+            .iter()
+            .filter_map(|r| r.split_once('@'))
+            .map(|(_, d)| d.to_owned())
+            .collect();
+
+        digests.insert(image.digest);
+
+        Some(Self {
+            digests,
+            name: image.repo_tags.into_iter().next()
+        })
+    }
+}
+
+/// Query the digest a reference currently points to in its registry.
+fn registry_digest(reference: &str) -> Option<String> {
+    let digest = Command::new("skopeo")
+        .args([
+            "inspect",
+            "--format",
+            "{{.Digest}}",
+        ])
+        .arg(format!("docker://{reference}"))
+        .output_ok()
+        .map_err(|e| debug!("Failed to query registry digest for {reference}: {e}"))
+        .ok()?;
+
+    Some(
+        digest.trim().to_owned()
+    )
+}
+
+/// Compare the base image of every managed image against the latest local and registry digests,
+/// listing the definitions (and their dependents) that would benefit from a rebuild.
+pub fn outdated() -> Result<()> {
+    use colored::Colorize;
+    use comfy_table::Table;
+    use comfy_table::presets::NOTHING;
+
+    let images = Image::enumerate()?;
+    let defs   = Definition::enumerate()?;
+
+    // Registry checks are best-effort, since skopeo is an optional dependency.
+    let skopeo = Command::new("skopeo")
+        .arg("--version")
+        .output()
+        .is_ok();
+
+    let mut rows     = vec![];
+    let mut outdated = vec![];
+    let mut pinned   = vec![];
+
+    for image in &images {
+        let name = image.annotation("box.name")
+            .expect("Name annotation should be set");
+
+        let (Some(base), Some(reference)) = (image.annotation("box.base"), image.annotation("box.base-ref")) else {
+            info!("Image {} predates base image tracking - skipping it", image.id);
+
+            rows.push(vec![
+                name.into(),
+                "?".into(),
+                "-".into(),
+                Status::Unchecked.cell(),
+                Status::Unchecked.cell(),
+            ]);
+
+            continue
+        };
+
+        let local = LocalImage::find(reference);
+
+        let local_status = match &local {
+            Some(l) if l.digests.contains(base) => Status::Current,
+            Some(_) => Status::Newer,
+            None    => Status::Missing,
+        };
+
+        // Short names can't be resolved by skopeo, so prefer the local copy's full name.
+        let query = local
+            .as_ref()
+            .and_then(|l| l.name.as_deref())
+            .unwrap_or(reference);
+
+        // Images built from other definitions only exist locally.
+        let registry_status = if skopeo && !query.starts_with("localhost/") {
+            match registry_digest(query) {
+                // Registries may report the digest of a manifest list rather than the platform-specific
+                // image, which is fine as long as it's the same one the local copy was pulled from.
+                Some(d) if d == base => Status::Current,
+                Some(d) if local_status == Status::Current && local.as_ref().is_some_and(|l| l.digests.contains(&d)) => {
+                    Status::Current
+                },
+                Some(_) => Status::Newer,
+                None    => Status::Unreachable,
+            }
+        }
+        else {
+            Status::Unchecked
+        };
+
+        if local_status == Status::Newer || registry_status == Status::Newer {
+            outdated.push(name.to_owned());
+
+            if image.annotation("box.pinned").is_some() {
+                pinned.push(name.to_owned());
+            }
+        }
+
+        let digest = base
+            .strip_prefix("sha256:")
+            .unwrap_or(base);
+
+        rows.push(vec![
+            name.into(),
+            reference.into(),
+            digest[..digest.len().min(12)].into(),
+            local_status.cell(),
+            registry_status.cell(),
+        ]);
+    }
+
+    let mut table = Table::new();
+
+    table
+        .load_preset(NOTHING)
+        .set_header(["Definition", "Base Image", "Built On", "Local", "Registry"])
+        .add_rows(rows);
+
+    println!("{table}");
+
+    if !skopeo {
+        eprintln!(
+            "{}",
+            "skopeo is not installed, so registry digests were not checked.".bright_black()
+        );
+    }
+
+    if outdated.is_empty() {
+        eprintln!(
+            "{}",
+            "All base images are up to date.".bold().green()
+        );
+
+        return Ok(())
+    }
+
+    // Dependents share the outdated base image, so they're rebuilt too.
+    let mut dependents: Vec<String> = vec![];
+
+    loop {
+        let next = defs
+            .iter()
+            .find(|def| {
+                let name = def.name();
+
+                !outdated.iter().chain(&dependents).any(|n| n == name) &&
+                def
+                    .depends_on()
+                    .iter()
+                    .any(|d| outdated.contains(d) || dependents.contains(d))
+            });
+
+        match next {
+            Some(def) => dependents.push(def.name().to_owned()),
+            None => break
+        }
+    }
+
+    eprintln!(
+        "{} {}",
+        "Outdated:".bold().bright_white(),
+        outdated.join(", ").yellow().bold()
+    );
+
+    if !dependents.is_empty() {
+        eprintln!(
+            "{} {}",
+            "Dependents:".bold().bright_white(),
+            dependents.join(", ").yellow()
+        );
+    }
+
+    let all: Vec<_> = outdated
+        .iter()
+        .chain(&dependents)
+        .map(String::as_str)
+        .collect();
+
+    eprintln!(
+        "Run {} to rebuild them.",
+        format!("bx build --force {}", all.join(" ")).yellow()
+    );
+
+    if !pinned.is_empty() {
+        eprintln!(
+            "Some of these use pinned base images, so run {} first.",
+            format!("bx lock --update {}", pinned.join(" ")).yellow()
+        );
+    }
+
+    Ok(())
+}
@@ -1,9 +1,13 @@
 use std::collections::HashMap;
-use std::process::Command;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitCode, ExitStatus};
+use std::sync::Mutex;
 
 use serde::Deserialize;
 
 use crate::prelude::*;
+use crate::api::{self, Client};
 use crate::CommandExt;
 
 pub type Containers = Vec<Container>;
@@ -25,21 +29,72 @@ pub const ANNOTATIONS: [&str; 12] = [
     "secret"
 ];
 
+/// Host environment variables forwarded into every `enter` and `exec` session, if set.
+pub const EXEC_ENV: [&str; 7] = [
+    "TERM",
+    "COLORTERM",
+    "LANG",
+    "LC_ALL",
+    "DISPLAY",
+    "WAYLAND_DISPLAY",
+    "XAUTHORITY",
+];
+
+/// Managed containers, as of the last call to [`Container::enumerate`].
+static CONTAINER_CACHE: Mutex<Option<Containers>> = Mutex::new(None);
+
+/// Managed images, as of the last call to [`Image::enumerate`].
+static IMAGE_CACHE: Mutex<Option<Images>> = Mutex::new(None);
+
+/// Discard cached enumerations, so changes made since are picked up.
+/// 
+/// Must be called after anything that creates, removes or re-tags containers or images.
+pub fn invalidate_cache() {
+    debug!("Invalidating container and image caches");
+
+    *CONTAINER_CACHE.lock().expect("Container cache should not be poisoned") = None;
+    *IMAGE_CACHE.lock().expect("Image cache should not be poisoned") = None;
+}
+
 /// Represents a Podman container.
 /// 
 /// Deserialized from Podman command line JSON; not guaranteed to be up to date!
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Container {
     pub id          : String,
     pub image       : String,
     pub state       : String,
     pub annotations : HashMap<String, String>,
+    pub mounts      : Vec<Mount>,
+}
+
+/// Represents a mount inside a Podman container.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Mount {
+    /// The type of the mount (`bind`, `volume`...)
+    #[serde(rename = "Type")]
+    pub kind        : String,
+    /// The path of the mount on the host.
+    #[serde(rename = "Source")]
+    pub source      : PathBuf,
+    /// The path of the mount inside the container.
+    #[serde(rename = "Destination")]
+    pub destination : PathBuf,
 }
 
 impl Container {
     /// Given an ID (hash or human-readable name), attempt to fetch and deserialize the corresponding
     /// container.
     pub fn from_id(id: &str) -> Result<Self> {
+        let out = Self::inspect(&[id])?
+                .pop()
+                .expect("Container JSON should always have at least one element");
+        
+        Ok(out)
+    }
+
+    /// Fetch and deserialize any number of containers with a single `podman inspect` call.
+    fn inspect(ids: &[&str]) -> Result<Containers> {
         // These structs are all intermediary; they are only needed
         // to represent the nested nature of Podman's JSON output.
 
@@ -64,57 +119,97 @@ impl Container {
             #[serde(rename = "ImageName")]
             image : String,
             #[serde(rename = "Config")]
-            config: Config
+            config: Config,
+            #[serde(rename = "Mounts", default)]
+            mounts: Vec<Mount>,
         }
 
-        let raw_json = Command::new("podman")
-            .args([
-                "inspect",
-                "--type",
-                "container",
-                "--format",
-                "json",
-                id
-            ])
-            .output_ok()
-            .context("Failed to inspect container JSON")?;
+        if ids.is_empty() {
+            return Ok(vec![])
+        }
 
-        // Podman is weird and always returns the JSON in an array, even when there can only be one element.
-        let mut raw: Vec<Raw> = serde_json::from_str(&raw_json)
-                .context("Failed to deserialize container JSON")?;
+        let raw: Vec<Raw> = match Client::connect()? {
+            // The API only inspects one container at a time.
+            Some(api) => ids
+                .iter()
+                .map(|id| api.get(&format!("/containers/{}/json", api::encode(id))))
+                .collect::<Result<_>>()
+                .context("Failed to inspect container JSON")?,
+            None => {
+                let raw_json = Command::new("podman")
+                    .args([
+                        "inspect",
+                        "--type",
+                        "container",
+                        "--format",
+                        "json",
+                    ])
+                    .args(ids)
+                    .output_ok()
+                    .context("Failed to inspect container JSON")?;
 
-        let Raw { id, image, state, config } = raw
-                .pop()
-                .expect("Container JSON should always have at least one element");
+                // Podman is weird and always returns the JSON in an array, even when there can only be one element.
+                serde_json::from_str(&raw_json)
+                    .context("Failed to deserialize container JSON")?
+            }
+        };
+
+        let out = raw
+            .into_iter()
+            .map(|Raw { id, image, state, config, mounts }| Self {
+                id,
+                image,
+                state       : state.status,
+                annotations : config.annotations,
+                mounts
+            })
+            .collect();
 
-        Ok(Self {
-            id,
-            image,
-            state       : state.status,
-            annotations : config.annotations
-        })
+        Ok(out)
     }
 
     /// Enumerate all containers *managed by Box* (**NOT** every container on the system.)
+    /// 
+    /// Box marks what it manages with annotations, which listings don't include - so every container
+    /// on the system has to be inspected. The command line does this in one call, but the API backend
+    /// makes one request per container.
+    /// 
+    /// Results are cached until [`invalidate_cache`] is called.
     pub fn enumerate() -> Result<Containers> {
-        let o = Command::new("podman")
-            .args([
-                "ps",
-                "-a",
-                "--format",
-                "{{.ID}}"
-            ])
-            .output_ok()
-            .context("Failed to enumerate all container IDs")?;
+        let mut cache = CONTAINER_CACHE
+            .lock()
+            .expect("Container cache should not be poisoned");
+
+        if let Some(containers) = cache.as_ref() {
+            debug!("Using cached container enumeration");
+            return Ok(containers.clone())
+        }
+
+        let ids = match Client::connect()? {
+            Some(api) => api.list("/containers/json?all=true")
+                .context("Failed to enumerate all container IDs")?,
+            None => Command::new("podman")
+                .args([
+                    "ps",
+                    "-a",
+                    "--format",
+                    "{{.ID}}"
+                ])
+                .output_ok()
+                .context("Failed to enumerate all container IDs")?
+                .lines()
+                .map(str::to_owned)
+                .collect()
+        };
+
+        let ids: Vec<_> = ids
+            .iter()
+            .map(String::as_str)
+            .collect();
         
         let mut out = vec![];
                 
-        for container in o
-            .lines()
-            .map(Container::from_id) 
-        {   
-            let container = container?;
-
+        for container in Self::inspect(&ids)? {   
             let manager = container
                 .annotations
                 .get("manager")
@@ -129,12 +224,20 @@ impl Container {
                 out.push(container);
             }
         }
+
+        *cache = Some(out.clone());
         
         Ok(out)
     }
 
     /// Check whether or not a container with the provided ID exists.
     pub fn exists(id: &str) -> Result<bool> {
+        if let Some(api) = Client::connect()? {
+            return api
+                .exists(&format!("/containers/{}/exists", api::encode(id)))
+                .context("Failed to check if container exists")
+        }
+
         let output = Command::new("podman")
             .args([
                 "container",
@@ -167,11 +270,21 @@ impl Container {
     pub fn start(&self) -> Result<()> {
         debug!("Starting container {}...", self.id);
 
-        Command::new("podman")
-            .arg("start")
-            .arg(&self.id)
-            .output_ok()
-            .context("Failed to start container")?;
+        if let Some(api) = Client::connect()? {
+            let path = format!("/containers/{}/start", api::encode(&self.id));
+
+            api.post(&path, None)
+                .context("Failed to start container")?;
+        }
+        else {
+            Command::new("podman")
+                .arg("start")
+                .arg(&self.id)
+                .output_ok()
+                .context("Failed to start container")?;
+        }
+
+        invalidate_cache();
         
         Ok(())
     }
@@ -180,15 +293,25 @@ impl Container {
     pub fn restart(&self) -> Result<()> {
         debug!("Restarting container {}...", self.id);
 
-        Command::new("podman")
-            .args([
-                "restart",
-                "-t",
-                "0"
-            ])
-            .arg(&self.id)
-            .output_ok()
-            .context("Failed to restart container")?;
+        if let Some(api) = Client::connect()? {
+            let path = format!("/containers/{}/restart?t=0", api::encode(&self.id));
+
+            api.post(&path, None)
+                .context("Failed to restart container")?;
+        }
+        else {
+            Command::new("podman")
+                .args([
+                    "restart",
+                    "-t",
+                    "0"
+                ])
+                .arg(&self.id)
+                .output_ok()
+                .context("Failed to restart container")?;
+        }
+
+        invalidate_cache();
 
         Ok(())
     }
@@ -197,15 +320,25 @@ impl Container {
     pub fn stop(&self) -> Result<()> {
         debug!("Stopping container {}...", self.id);
 
-        Command::new("podman")
-            .args([
-                "stop",
-                "-t",
-                "0"
-            ])
-            .arg(&self.id)
-            .output_ok()
-            .context("Failed to stop container")?;
+        if let Some(api) = Client::connect()? {
+            let path = format!("/containers/{}/stop?timeout=0", api::encode(&self.id));
+
+            api.post(&path, None)
+                .context("Failed to stop container")?;
+        }
+        else {
+            Command::new("podman")
+                .args([
+                    "stop",
+                    "-t",
+                    "0"
+                ])
+                .arg(&self.id)
+                .output_ok()
+                .context("Failed to stop container")?;
+        }
+
+        invalidate_cache();
 
         Ok(())
     }
@@ -214,68 +347,237 @@ impl Container {
     pub fn down(&self) -> Result<()> {
         debug!("Removing container {}...", self.id);
         
-        Command::new("podman")
-           .args([
-                "rm",
-                "-ft",
-                "0"
-           ])
-           .arg(&self.id)
-           .output_ok()
-           .context("Failed to remove container")?;
+        if let Some(api) = Client::connect()? {
+            let path = format!("/containers/{}?force=true&timeout=0", api::encode(&self.id));
+
+            api.delete(&path)
+                .context("Failed to remove container")?;
+        }
+        else {
+            Command::new("podman")
+               .args([
+                    "rm",
+                    "-ft",
+                    "0"
+               ])
+               .arg(&self.id)
+               .output_ok()
+               .context("Failed to remove container")?;
+        }
+
+        invalidate_cache();
 
         Ok(())
     }
 
-    /// Execute `$SHELL` inside the container.
+    /// Translate a path on the host to the corresponding path inside the container,
+    /// if it's under one of the container's bind mounts.
+    pub fn container_path(&self, host: &Path) -> Option<PathBuf> {
+        self
+            .mounts
+            .iter()
+            .filter(|m| m.kind == "bind")
+            .filter_map(|m| {
+                let rest = host.strip_prefix(&m.source).ok()?;
+
+                // Joining an empty path would add a trailing slash.
+                let path = match rest.as_os_str().is_empty() {
+                    true  => m.destination.to_owned(),
+                    false => m.destination.join(rest)
+                };
+
+                Some((
+                    m.source.components().count(),
+                    path
+                ))
+            })
+            // Prefer the most specific mount, in case they're nested.
+            .max_by_key(|(depth, _)| *depth)
+            .map(|(_, path)| path)
+    }
+
+    /// Determine the container's equivalent of the host's working directory, if it has one.
+    fn workdir(&self) -> Option<PathBuf> {
+        use colored::Colorize;
+
+        let cwd = std::env::current_dir().ok()?;
+
+        match self.container_path(&cwd) {
+            Some(path) => Some(path),
+            None => {
+                eprintln!(
+                    "{}",
+                    format!(
+                        "{} isn't mounted in this container, so the default working directory is used.",
+                        cwd.to_string_lossy()
+                    ).bright_black()
+                );
+
+                None
+            }
+        }
+    }
+
+    /// Determine the environment of a session, as `KEY=VAL` pairs.
+    /// 
+    /// Variables listed in [`EXEC_ENV`], the image's `exec-env` configuration and `extra` are
+    /// forwarded from the host; entries in `extra` can also set a value with `KEY=VAL`.
+    fn environment(&self, extra: &[String]) -> Vec<OsString> {
+        let baked = self
+            .annotation("box.exec-env")
+            .into_iter()
+            .flat_map(|v| v.split('\x1F'))
+            .filter(|v| !v.is_empty());
+
+        let mut out = vec![];
+
+        for var in EXEC_ENV.into_iter().chain(baked).chain(extra.iter().map(String::as_str)) {
+            if var.contains('=') {
+                out.push(var.into());
+
+                continue
+            }
+
+            // Unset variables are skipped, rather than being set to an empty value.
+            if let Some(value) = std::env::var_os(var) {
+                let mut pair = OsString::from(var);
+
+                pair.push("=");
+                pair.push(value);
+
+                out.push(pair);
+            }
+        }
+
+        out
+    }
+
+    /// Determine the `podman exec` arguments needed to set up the working directory and environment of a session.
+    fn session_args(&self, env: &[String]) -> Vec<OsString> {
+        let mut out = vec![];
+
+        if let Some(path) = self.workdir() {
+            out.push("--workdir".into());
+            out.push(path.into());
+        }
+
+        for pair in self.environment(env) {
+            out.push("--env".into());
+            out.push(pair);
+        }
+
+        out
+    }
+
+    /// Execute `$SHELL` inside the container, returning the exit code of the shell.
     /// 
     /// The value of `$SHELL` inside the container is used rather than the one on the host.
-    pub fn enter(&self) -> Result<()> {
-            Command::new("podman")
-                .arg("exec")
-                .arg("-it")
-                .arg(&self.id)
-                .arg("sh")
-                .arg("-c")
-                .arg("exec $SHELL")
-                .spawn()
-                .context("Fault when spawning shell inside container")?
-                .wait()?;
+    pub fn enter(&self, env: &[String]) -> Result<ExitCode> {
+        let status = Command::new("podman")
+            .arg("exec")
+            .arg("-it")
+            .args(self.session_args(env))
+            .arg(&self.id)
+            .arg("sh")
+            .arg("-c")
+            .arg("exec $SHELL")
+            .spawn()
+            .context("Fault when spawning shell inside container")?
+            .wait()
+            .context("Fault when waiting for shell inside container")?;
         
-        Ok(())
+        Ok(exit_code(status))
     }
 
-    /// Execute the provided command inside the container.
-    pub fn exec(&self, path: &str, args: &[String]) -> Result<()> {
-        Command::new("podman")
+    /// Execute the provided command inside the container, returning its exit code.
+    /// 
+    /// A pseudo-TTY is only allocated if `tty` is set. Sessions with a pseudo-TTY always go through
+    /// the command line, as the API backend can't forward terminal input.
+    pub fn exec(&self, path: &str, args: &[String], tty: bool, env: &[String]) -> Result<ExitCode> {
+        if let (false, Some(api)) = (tty, Client::connect()?) {
+            let cmd: Vec<_> = std::iter::once(path)
+                .chain(args.iter().map(String::as_str))
+                .map(str::to_owned)
+                .collect();
+
+            let env: Vec<_> = self
+                .environment(env)
+                .into_iter()
+                .map(|pair| pair.to_string_lossy().into_owned())
+                .collect();
+
+            let workdir = self
+                .workdir()
+                .map(|p| p.to_string_lossy().into_owned());
+
+            let code = api
+                .exec(&self.id, &cmd, &env, workdir.as_deref())
+                .context("Fault when executing process inside container")?;
+
+            return Ok(clamp_code(code))
+        }
+
+        let status = Command::new("podman")
             .arg("exec")
-            .arg("-it")
+            .arg(if tty { "-it" } else { "-i" })
+            .args(self.session_args(env))
             .arg(&self.id)
             .arg(path)
             .args(args)
             .spawn()
             .context("Fault when spawning process inside container")?
-            .wait()?;
+            .wait()
+            .context("Fault when waiting for process inside container")?;
         
-        Ok(())
+        Ok(exit_code(status))
     }
 }
 
 /// Represents a Podman OCI iamge.
 /// 
 /// Deserialized from Podman command line JSON; not guaranteed to be up to date!
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct Image {
     #[serde(rename = "Id")]
     pub id          : String,
     #[serde(rename = "Annotations")]
     pub annotations : HashMap<String, String>,
+    #[serde(rename = "RepoTags", default)]
+    pub tags        : Vec<String>,
+    /// The size of the image in bytes.
+    #[serde(rename = "Size", default)]
+    pub size        : u64,
+    /// When the image was created, as an RFC 3339 timestamp.
+    #[serde(rename = "Created", default)]
+    pub created     : Option<String>,
 }
 
 impl Image {
     /// Given an ID (hash or human-readable name), attempt to fetch and deserialize the corresponding
     /// image.
     pub fn from_id(id: &str) -> Result<Self> {
+        let out = Self::inspect(&[id])?
+                .pop()
+                .expect("Image JSON should always have at least one element");
+        
+        Ok(out)
+    }
+
+    /// Fetch and deserialize any number of images with a single `podman inspect` call.
+    pub fn inspect(ids: &[&str]) -> Result<Images> {
+        if ids.is_empty() {
+            return Ok(vec![])
+        }
+
+        if let Some(api) = Client::connect()? {
+            // The API only inspects one image at a time.
+            return ids
+                .iter()
+                .map(|id| api.get(&format!("/images/{}/json", api::encode(id))))
+                .collect::<Result<_>>()
+                .context("Failed to inspect image JSON")
+        }
+
         let raw_json = Command::new("podman")
             .args([
                 "inspect",
@@ -283,43 +585,97 @@ impl Image {
                 "image",
                 "--format",
                 "json",
-                id
             ])
+            .args(ids)
             .output_ok()
             .context("Failed to inspect image JSON")?;
 
         // Podman is weird and always returns the JSON in an array, even when there can only be one element.
-        let mut raw: Vec<Self> = serde_json::from_str(&raw_json)
-                .context("Failed to deserialize image JSON")?;
-
-        let out = raw
-                .pop()
-                .expect("Container JSON should always have at least one element");
-        
-        Ok(out)
+        serde_json::from_str(&raw_json)
+            .context("Failed to deserialize image JSON")
     }
 
-    /// Enumerate all images *managed by Box* (**NOT** every image on the system.)
-    pub fn enumerate() -> Result<Images> {
-        let o = Command::new("podman")
+    /// Check whether or not an image with the provided ID exists.
+    pub fn exists(id: &str) -> Result<bool> {
+        if let Some(api) = Client::connect()? {
+            return api
+                .exists(&format!("/images/{}/exists", api::encode(id)))
+                .context("Failed to check if image exists")
+        }
+
+        let output = Command::new("podman")
             .args([
                 "image",
-                "ls",
-                "--format",
-                "{{.ID}}",
-                "--filter",
-                "dangling=false"
+                "exists",
+                id
             ])
-            .output_ok()
-            .context("Failed to enumerate all image IDs")?;
+            .output()
+            .context("Failed to check if image exists")?;
+
+        Ok(
+            output.status.success()
+        )
+    }
+
+    /// Enumerate all images *managed by Box* (**NOT** every image on the system.)
+    /// 
+    /// Box marks what it manages with annotations, which listings don't include - so every image
+    /// on the system has to be inspected. The command line does this in one call, but the API backend
+    /// makes one request per image.
+    /// 
+    /// Results are cached until [`invalidate_cache`] is called.
+    pub fn enumerate() -> Result<Images> {
+        let mut cache = IMAGE_CACHE
+            .lock()
+            .expect("Image cache should not be poisoned");
+
+        if let Some(images) = cache.as_ref() {
+            debug!("Using cached image enumeration");
+            return Ok(images.clone())
+        }
+
+        let filters = api::encode(r#"{"dangling":["false"]}"#);
+
+        let ids = match Client::connect()? {
+            Some(api) => api.list(&format!("/images/json?filters={filters}"))
+                .context("Failed to enumerate all image IDs")?,
+            None => Command::new("podman")
+                .args([
+                    "image",
+                    "ls",
+                    "--format",
+                    "{{.ID}}",
+                    "--filter",
+                    "dangling=false"
+                ])
+                .output_ok()
+                .context("Failed to enumerate all image IDs")?
+                .lines()
+                .map(str::to_owned)
+                .collect()
+        };
+
+        // Images are listed once per tag, so the same ID can show up several times.
+        let mut ids: Vec<_> = ids
+            .iter()
+            .map(String::as_str)
+            .collect();
+
+        ids.sort_unstable();
+        ids.dedup();
 
         let mut out = vec![];
             
-        for image in o
-            .lines()
-            .map(Image::from_id) 
-        {   
-            let image = image?;
+        for image in Self::inspect(&ids)? {
+            // Old versions kept for rollbacks aren't current, so they're ignored.
+            let historical = !image.tags.is_empty() && image
+                .tags
+                .iter()
+                .all(|t| t.starts_with(crate::history::REPOSITORY));
+
+            if historical {
+                continue
+            }
 
             if let Some("box") = image.annotation("manager") {
                 debug!(
@@ -330,6 +686,8 @@ impl Image {
                 out.push(image);
             }
         }
+
+        *cache = Some(out.clone());
         
         Ok(out)
     }
@@ -339,41 +697,59 @@ impl Image {
     /// `replace` controls whether or not the new container should overwrite
     /// an existing one with the same name.
     pub fn instantiate(&self, replace: bool) -> Result<()> {
-        self.instantiate_ext(replace, &[])
+        self
+            .instantiate_ext(replace, &[], false)
+            .map(drop)
     }
 
     /// Extended instantiation method, with support for overriding the default command
     /// (ephemeral mode.)
-    pub fn instantiate_ext(&self, replace: bool, ephemeral_args: &[String]) -> Result<()> {
+    /// 
+    /// In ephemeral mode, the exit code of the command is returned, and a pseudo-TTY
+    /// is only allocated if `tty` is set.
+    pub fn instantiate_ext(&self, replace: bool, ephemeral_args: &[String], tty: bool) -> Result<ExitCode> {
         let name = self.annotation("box.name")
             .expect("Name annotation should be set");
 
-        let hash = self.annotation("box.hash")
-            .expect("Hash annotation should be set");
+        // Ephemeral containers need their output attached, so they always go through the command line.
+        if let (true, Some(api)) = (ephemeral_args.is_empty(), Client::connect()?) {
+            if let Some(spec) = self.spec() {
+                if replace && Container::exists(name)? {
+                    api.delete(&format!("/containers/{}?force=true&timeout=0", api::encode(name)))
+                        .context("Fault when replacing existing container")?;
+                }
 
-        let mut args = vec![];
+                let id = api
+                    .create(&spec)
+                    .context("Fault when instantiating image")?;
 
-        for a in ANNOTATIONS {
-            let key = format!("box.{a}");
-            
-            let Some(value) = self.annotation(&key) else {
-                continue
-            };
+                api.post(&format!("/containers/{id}/start"), None)
+                    .context("Fault when starting new container")?;
 
+                invalidate_cache();
+
+                return Ok(ExitCode::SUCCESS)
+            }
+
+            debug!("Runtime configuration of {name} isn't supported by the API backend, using the command line");
+        }
+
+        let mut args = vec![];
+
+        for (a, v) in self.runtime_config() {
             if a == "args" {
-                for v in value.split('\x1F') {
-                    args.push(v.to_owned())
-                }
+                args.push(v.to_owned())
             } else {
-                let flag = format!("--{a}");
-
-                for v in value.split('\x1F') {
-                    args.push(flag.clone());
-                    args.push(v.to_owned());
-                }
+                args.push(format!("--{a}"));
+                args.push(v.to_owned());
             }
         }
 
+        for (key, value) in self.container_annotations() {
+            args.push("--annotation".to_owned());
+            args.push(format!("{key}={value}"));
+        }
+
         if replace {
             args.push(
                 "--replace".to_owned()
@@ -381,7 +757,7 @@ impl Image {
         }
 
         let name_args = match ephemeral_args.is_empty() {
-            false => vec!["--rm", "-it", "--hostname", name],
+            false => vec!["--rm", if tty { "-it" } else { "-i" }, "--hostname", name],
             true  => vec!["-d", "--name", name, "--hostname", name]
         };
 
@@ -391,23 +767,150 @@ impl Image {
             .arg("run")
             .args(name_args)
             .args(args)
-            .args([
-                "--annotation",
-                "manager=box"
-            ])
-            .arg("--annotation")
-            .arg(format!("box.name={name}"))
-            .arg("--annotation")
-            .arg(format!("box.hash={hash}"))
             .arg(name)
             .args(ephemeral_args);
 
-        match ephemeral_args.is_empty() {
-            false =>  c.spawn_ok(),
-            true  => c.output_ok().map(drop)
-        }.context("Fault when instantiating image")?;
+        if !ephemeral_args.is_empty() {
+            let status = c
+                .spawn()
+                .context("Fault when instantiating image")?
+                .wait()
+                .context("Fault when waiting for ephemeral container")?;
 
-        Ok(())
+            invalidate_cache();
+
+            return Ok(exit_code(status))
+        }
+
+        c
+            .output_ok()
+            .context("Fault when instantiating image")?;
+
+        invalidate_cache();
+
+        Ok(ExitCode::SUCCESS)
+    }
+
+    /// The annotations every container instantiated from the image is given, as `(key, value)` pairs.
+    fn container_annotations(&self) -> Vec<(&'static str, &str)> {
+        let mut out = vec![
+            ("manager", "box"),
+            ("box.name", self.annotation("box.name").expect("Name annotation should be set")),
+            ("box.hash", self.annotation("box.hash").expect("Hash annotation should be set")),
+        ];
+
+        // Needed by 'enter' and 'exec', which only have the container to go on.
+        if let Some(vars) = self.annotation("box.exec-env") {
+            out.push(("box.exec-env", vars));
+        }
+
+        out
+    }
+
+    /// Translate the image's runtime configuration into a spec generator for the API backend's
+    /// `containers/create` endpoint.
+    /// 
+    /// Returns `None` if any of the configuration has no translation (raw `args`, resource limits, secrets
+    /// and less common options), in which case the command line has to be used instead.
+    fn spec(&self) -> Option<serde_json::Value> {
+        use serde_json::{json, Map, Value};
+
+        let name = self.annotation("box.name")
+            .expect("Name annotation should be set");
+
+        let annotations: Map<_, _> = self
+            .container_annotations()
+            .into_iter()
+            .map(|(k, v)| (k.to_owned(), v.into()))
+            .collect();
+
+        let mut spec = json!({
+            "name"        : name,
+            "hostname"    : name,
+            "image"       : name,
+            "annotations" : annotations,
+        });
+
+        let mut push = |key: &str, value: Value| {
+            let list = spec
+                .as_object_mut()
+                .expect("Spec should be an object")
+                .entry(key)
+                .or_insert_with(|| json!([]));
+
+            list
+                .as_array_mut()
+                .expect("Spec list should be an array")
+                .push(value)
+        };
+
+        let mut scalars = vec![];
+
+        for (option, value) in self.runtime_config() {
+            match option {
+                "cap-add"  => push("cap_add", value.into()),
+                "cap-drop" => push("cap_drop", value.into()),
+                // Only plain device paths, not HOST:CONTAINER[:PERMISSIONS] mappings.
+                "device" if !value.contains(':') => push("devices", json!({ "path": value })),
+                "security-opt" => match value.split_once('=') {
+                    Some(("label", opt)) => push("selinux_opts", opt.into()),
+                    None if value == "no-new-privileges" => scalars.push(("no_new_privileges", json!(true))),
+                    _ => return None
+                },
+                "userns" => {
+                    let (mode, opts) = value
+                        .split_once(':')
+                        .unwrap_or((value, ""));
+
+                    scalars.push(("userns", json!({ "nsmode": mode, "value": opts })))
+                },
+                "restart" => {
+                    let (policy, tries) = value
+                        .split_once(':')
+                        .unwrap_or((value, ""));
+
+                    scalars.push(("restart_policy", policy.into()));
+
+                    if !tries.is_empty() {
+                        scalars.push(("restart_tries", tries.parse::<u64>().ok()?.into()))
+                    }
+                },
+                "mount" => push("mounts", mount_spec(value)?),
+                _ => return None
+            }
+        }
+
+        let object = spec
+            .as_object_mut()
+            .expect("Spec should be an object");
+
+        for (key, value) in scalars {
+            object.insert(key.into(), value);
+        }
+
+        Some(spec)
+    }
+
+    /// Collect the runtime configuration baked into the image by `CFG`, as
+    /// `(option, value)` pairs in [`ANNOTATIONS`] order.
+    /// 
+    /// Multi-valued annotations are split, yielding one pair per value.
+    pub fn runtime_config(&self) -> Vec<(&'static str, &str)> {
+        let mut out = vec![];
+
+        for a in ANNOTATIONS {
+            let key = format!("box.{a}");
+
+            let Some(value) = self.annotation(&key) else {
+                continue
+            };
+
+            for v in value.split('\x1F') {
+                out.push((a, v))
+            }
+        }
+
+        out
     }
 
     /// Get the value of an annotation, if it exists.
@@ -419,6 +922,70 @@ impl Image {
     }
 }
 
+/// Translate a `--mount` argument into a spec generator mount, if it only uses options
+/// the API backend understands.
+fn mount_spec(arg: &str) -> Option<serde_json::Value> {
+    let mut kind = "volume";
+    let mut source = None;
+    let mut destination = None;
+    let mut options = vec![];
+
+    for field in arg.split(',') {
+        let (key, value) = field
+            .split_once('=')
+            .unwrap_or((field, "true"));
+
+        match (key, value) {
+            ("type", v) => kind = v,
+            ("src" | "source", v) => source = Some(v),
+            ("dst" | "destination" | "target", v) => destination = Some(v),
+            ("ro" | "readonly", "true") => options.push("ro"),
+            ("ro" | "readonly", "false") => options.push("rw"),
+            ("relabel", "shared") => options.push("z"),
+            ("relabel", "private") => options.push("Z"),
+            ("bind-propagation", v) => options.push(v),
+            ("shared" | "slave" | "private" | "rshared" | "rslave" | "rprivate", "true") => options.push(key),
+            _ => return None
+        }
+    }
+
+    // Volumes are specified separately from mounts, so leave them to the command line.
+    let source = match (kind, source) {
+        ("bind", Some(source)) => source,
+        ("tmpfs" | "devpts", source) => source.unwrap_or(kind),
+        _ => return None
+    };
+
+    Some(serde_json::json!({
+        "type"        : kind,
+        "source"      : source,
+        "destination" : destination?,
+        "options"     : options,
+    }))
+}
+
+/// Convert the exit status of a child process into an exit code for Box,
+/// following the shell convention of 128 plus the signal number for killed processes.
+pub fn exit_code(status: ExitStatus) -> ExitCode {
+    use std::os::unix::process::ExitStatusExt;
+
+    let code = match (status.code(), status.signal()) {
+        (Some(code), _) => code,
+        (_, Some(signal)) => 128 + signal,
+        _ => 1
+    };
+
+    clamp_code(code)
+}
+
+/// Convert a raw exit code (such as one reported by the API) into an exit code for Box.
+/// Codes that don't fit in a byte are reported as a generic failure rather than truncated.
+pub fn clamp_code(code: i32) -> ExitCode {
+    u8::try_from(code)
+        .map(ExitCode::from)
+        .unwrap_or(ExitCode::FAILURE)
+}
+
 /// Append a value to the specified annotation on the provided container. Each item is separated with
 /// `\x1F` (the ASCII unit separator character.)
 pub fn push_annotation(ctr: &str, key: &str, data: &str) -> Result<()> {
@@ -463,3 +1030,108 @@ pub fn write_annotation(ctr: &str, key: &str, data: Vec<&str>) -> Result<()> {
         .spawn_ok()
         .context("Fault when writing annotation to working container")
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn image(annotations: &[(&str, &str)]) -> Image {
+        let mut all = HashMap::from([
+            ("manager".to_owned(), "box".to_owned()),
+            ("box.name".to_owned(), "dev".to_owned()),
+            ("box.hash".to_owned(), "abc".to_owned()),
+        ]);
+
+        for (k, v) in annotations {
+            all.insert(k.to_string(), v.to_string());
+        }
+
+        Image {
+            id          : "1234".to_owned(),
+            annotations : all,
+            tags        : vec![],
+            size        : 0,
+            created     : None,
+        }
+    }
+
+    #[test]
+    fn mount_spec_translates_supported_options() {
+        assert_eq!(
+            mount_spec("type=bind,src=/home/me,dst=/src,ro=true,relabel=shared,rslave").unwrap(),
+            json!({ "type": "bind", "source": "/home/me", "destination": "/src", "options": ["ro", "z", "rslave"] })
+        );
+
+        assert_eq!(
+            mount_spec("type=tmpfs,destination=/tmp").unwrap(),
+            json!({ "type": "tmpfs", "source": "tmpfs", "destination": "/tmp", "options": [] })
+        );
+    }
+
+    #[test]
+    fn mount_spec_rejects_unsupported_mounts() {
+        // Bind mounts need a source, and everything needs a destination.
+        assert_eq!(mount_spec("type=bind,dst=/src"), None);
+        assert_eq!(mount_spec("type=tmpfs"), None);
+        // Volumes (the default type) and unknown options are left to the command line.
+        assert_eq!(mount_spec("src=data,dst=/data"), None);
+        assert_eq!(mount_spec("type=bind,src=/a,dst=/b,idmap"), None);
+    }
+
+    #[test]
+    fn spec_includes_configuration_and_annotations() {
+        let image = image(&[
+            ("box.cap-add", "SYS_PTRACE\x1FNET_ADMIN"),
+            ("box.device", "/dev/fuse"),
+            ("box.userns", "keep-id:uid=1000"),
+            ("box.security-opt", "label=disable"),
+            ("box.restart", "on-failure:3"),
+            ("box.exec-env", "EDITOR"),
+        ]);
+
+        assert_eq!(
+            image.spec().unwrap(),
+            json!({
+                "name"           : "dev",
+                "hostname"       : "dev",
+                "image"          : "dev",
+                "annotations"    : {
+                    "manager"      : "box",
+                    "box.name"     : "dev",
+                    "box.hash"     : "abc",
+                    "box.exec-env" : "EDITOR",
+                },
+                "cap_add"        : ["SYS_PTRACE", "NET_ADMIN"],
+                "devices"        : [{ "path": "/dev/fuse" }],
+                "userns"         : { "nsmode": "keep-id", "value": "uid=1000" },
+                "selinux_opts"   : ["disable"],
+                "restart_policy" : "on-failure",
+                "restart_tries"  : 3,
+            })
+        );
+    }
+
+    #[test]
+    fn spec_falls_back_for_unsupported_configuration() {
+        for (key, value) in [
+            ("box.args", "--init"),
+            ("box.memory", "2g"),
+            ("box.device", "/dev/a:/dev/b"),
+            ("box.security-opt", "seccomp=unconfined"),
+            ("box.mount", "type=bind,dst=/src"),
+        ] {
+            assert_eq!(image(&[(key, value)]).spec(), None, "{key}={value}");
+        }
+    }
+
+    #[test]
+    fn clamp_code_keeps_codes_in_range() {
+        assert_eq!(clamp_code(0), ExitCode::SUCCESS);
+        assert_eq!(clamp_code(3), ExitCode::from(3));
+        assert_eq!(clamp_code(255), ExitCode::from(255));
+        assert_eq!(clamp_code(256), ExitCode::FAILURE);
+        assert_eq!(clamp_code(-1), ExitCode::FAILURE);
+    }
+}
@@ -0,0 +1,344 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::process::Command;
+
+use serde::Deserialize;
+
+use crate::prelude::*;
+use crate::podman::*;
+use crate::CommandExt;
+
+/// Why an object is eligible for pruning.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Reason {
+    /// An image that has been replaced by a newer build.
+    Superseded,
+    /// An image whose definition no longer exists.
+    Orphaned,
+    /// A checkpoint image.
+    Checkpoint,
+    /// A leftover Buildah working container.
+    WorkingContainer,
+}
+
+impl Reason {
+    fn describe(self) -> &'static str {
+        match self {
+            Self::Superseded       => "Superseded image",
+            Self::Orphaned         => "Definition removed",
+            Self::Checkpoint       => "Checkpoint image",
+            Self::WorkingContainer => "Working container",
+        }
+    }
+}
+
+/// An image or working container that can be removed.
+#[derive(Debug)]
+struct Candidate {
+    /// The ID (or, for checkpoints, reference) to remove.
+    id     : String,
+    /// A human-readable name for the object.
+    name   : String,
+    reason : Reason,
+    /// The size of the object in bytes, if known.
+    size   : Option<u64>,
+}
+
+impl Candidate {
+    fn remove(&self) -> Result<()> {
+        let (program, operation) = match self.reason {
+            Reason::WorkingContainer => ("buildah", "rm"),
+            _ => ("podman", "rmi"),
+        };
+
+        let output = Command::new(program)
+            .arg(operation)
+            .arg(&self.id)
+            .output()
+            .context(format!("Fault when running {program}"))?;
+
+        // Only the reason is of interest, as failures are summarized rather than fatal.
+        if !output.status.success() {
+            bail!(
+                "{}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            )
+        }
+
+        Ok(())
+    }
+}
+
+/// Find every Box-owned image and working container that is no longer needed.
+fn candidates() -> Result<Vec<Candidate>> {
+    #[derive(Debug, Deserialize)]
+    struct Listing {
+        #[serde(rename = "Id")]
+        id       : String,
+        #[serde(rename = "Size", default)]
+        size     : u64,
+        #[serde(rename = "Dangling", default)]
+        dangling : bool,
+        #[serde(rename = "Names", default)]
+        names    : Vec<String>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct WorkingContainer {
+        id            : String,
+        containername : String,
+    }
+
+    let listing: Vec<Listing> = serde_json::from_str(
+        &Command::new("podman")
+            .args([
+                "image",
+                "ls",
+                "--format",
+                "json"
+            ])
+            .output_ok()
+            .context("Failed to enumerate images")?
+    )
+    .context("Failed to deserialize image listing")?;
+
+    // Images backing a container can't be removed without removing the container first.
+    let in_use: HashSet<_> = Command::new("podman")
+        .args([
+            "ps",
+            "--all",
+            "--no-trunc",
+            "--format",
+            "{{.ImageID}}"
+        ])
+        .output_ok()
+        .context("Failed to enumerate containers")?
+        .lines()
+        .map(str::to_owned)
+        .collect();
+
+    let listing: Vec<_> = listing
+        .into_iter()
+        .filter(|entry| !in_use.contains(&entry.id))
+        .collect();
+
+    // Everything else is inspected in one go, rather than spawning Podman once per image.
+    let mut ids: Vec<_> = listing
+        .iter()
+        .map(|entry| entry.id.as_str())
+        .collect();
+
+    ids.sort_unstable();
+    ids.dedup();
+
+    let images: HashMap<_, _> = Image::inspect(&ids)?
+        .into_iter()
+        .map(|image| (image.id.clone(), image))
+        .collect();
+
+    let mut out = vec![];
+
+    for entry in listing {
+        let Some(image) = images.get(&entry.id) else {
+            continue
+        };
+
+        let reason = match image.annotation("manager") {
+            Some("box-checkpoint") => Reason::Checkpoint,
+            Some("box") if entry.dangling => Reason::Superseded,
+            Some("box") => {
+                let exists = image
+                    .annotation("box.path")
+                    .is_some_and(|p| Path::new(p).exists());
+
+                if exists {
+                    continue
+                }
+
+                Reason::Orphaned
+            },
+            _ => continue
+        };
+
+        // Checkpoints are removed by reference, so that only the tags Box created are touched.
+        // Tags share storage, so the size is only attributed to the first.
+        if reason == Reason::Checkpoint && !entry.names.is_empty() {
+            for (idx, name) in entry.names.iter().enumerate() {
+                out.push(Candidate {
+                    id     : name.to_owned(),
+                    name   : name.to_owned(),
+                    reason,
+                    size   : (idx == 0).then_some(entry.size),
+                })
+            }
+
+            continue
+        }
+
+        let name = entry
+            .names
+            .first()
+            .map(String::as_str)
+            .or(image.annotation("box.name"))
+            .unwrap_or(&entry.id)
+            .to_owned();
+
+        out.push(Candidate {
+            id   : entry.id,
+            name,
+            reason,
+            size : Some(entry.size),
+        })
+    }
+
+    let containers: Vec<WorkingContainer> = serde_json::from_str(
+        &Command::new("buildah")
+            .args([
+                "containers",
+                "--json"
+            ])
+            .output_ok()
+            .context("Failed to enumerate working containers")?
+    )
+    .context("Failed to deserialize working container listing")?;
+
+    for ctr in containers {
+        let manager = Command::new("buildah")
+            .args([
+                "inspect",
+                "--type",
+                "container",
+                "--format",
+                "{{index .ImageAnnotations \"manager\"}}",
+                &ctr.id
+            ])
+            .output_ok()
+            .context("Failed to inspect working container")?;
+
+        if manager.trim() == "box" {
+            out.push(Candidate {
+                id     : ctr.id,
+                name   : ctr.containername,
+                reason : Reason::WorkingContainer,
+                size   : None,
+            })
+        }
+    }
+
+    Ok(out)
+}
+
+/// Remove superseded and orphaned Box images, checkpoints and working containers.
+///
+/// The user is asked for confirmation first, unless `yes` is set.
+pub fn prune(yes: bool) -> Result<()> {
+    use colored::Colorize;
+    use comfy_table::Table;
+    use comfy_table::presets::NOTHING;
+    use dialoguer::Confirm;
+
+    let candidates = candidates()?;
+
+    if candidates.is_empty() {
+        eprintln!(
+            "{}",
+            "Nothing to prune.".bold().green()
+        );
+
+        return Ok(())
+    }
+
+    let rows = candidates
+        .iter()
+        .map(|c| [
+            c.name.clone(),
+            c.reason.describe().to_owned(),
+            c.size.map(format_size).unwrap_or_else(|| "-".to_owned())
+        ]);
+
+    let mut table = Table::new();
+
+    table
+        .load_preset(NOTHING)
+        .set_header(["Name", "Reason", "Size"])
+        .add_rows(rows);
+
+    println!("{table}");
+
+    // Checkpoints can share layers with each other (or their final image), so this is an upper bound.
+    let total: u64 = candidates
+        .iter()
+        .filter_map(|c| c.size)
+        .sum();
+
+    eprintln!(
+        "{} {}",
+        "Up to".bold().bright_white(),
+        format!("{} can be reclaimed.", format_size(total)).bold().green()
+    );
+
+    eprintln!(
+        "{}",
+        "Working containers are removed too, so don't prune while a build is running.".bright_black()
+    );
+
+    if !yes {
+        let confirm = Confirm::new()
+            .with_prompt(
+                format!("Remove {} object(s)?", candidates.len())
+            )
+            .interact()
+            .context("Fault when asking for user confirmation")?;
+
+        if !confirm {
+            return Ok(())
+        }
+    }
+
+    // Working containers go first, as they may be holding on to the images being removed.
+    let (containers, images): (Vec<_>, Vec<_>) = candidates
+        .iter()
+        .partition(|c| c.reason == Reason::WorkingContainer);
+
+    let mut failures = 0;
+
+    for candidate in containers.into_iter().chain(images) {
+        if let Err(err) = candidate.remove() {
+            eprintln!(
+                "{}{} {} {}",
+                "Warning".bold().yellow(),
+                ": failed to remove".bold().bright_white(),
+                candidate.name.bold().green(),
+                format!("({err})").bright_black()
+            );
+
+            failures += 1;
+        }
+    }
+
+    invalidate_cache();
+
+    if failures != 0 {
+        bail!("Failed to remove {failures} object(s)")
+    }
+
+    Ok(())
+}
+
+/// Format a size in bytes as a human-readable string.
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+
+    let mut size = bytes as f64;
+    let mut unit = 0;
+
+    while size >= 1000.0 && unit < UNITS.len() - 1 {
+        size /= 1000.0;
+        unit += 1;
+    }
+
+    match unit {
+        0 => format!("{bytes} {}", UNITS[0]),
+        _ => format!("{size:.1} {}", UNITS[unit])
+    }
+}
@@ -0,0 +1,308 @@
+use std::cell::{Cell, RefCell};
+use std::ffi::OsString;
+use std::path::Path;
+use std::process::Command;
+use std::rc::Rc;
+
+use rhai::{Array, Dynamic, Engine, EvalAltResult, Map, Scope};
+
+use crate::prelude::*;
+use crate::build::*;
+use crate::CommandExt;
+
+type NativeResult<T> = std::result::Result<T, Box<EvalAltResult>>;
+
+/// Directives that map directly onto `buildah config` flags.
+const CONFIG_DIRECTIVES: [(&str, &str); 10] = [
+    ("CMD", "--cmd"),
+    ("ENTRYPOINT", "--entrypoint"),
+    ("LABEL", "--label"),
+    ("EXPOSE", "--port"),
+    ("VOLUME", "--volume"),
+    ("USER", "--user"),
+    ("WORKDIR", "--workingdir"),
+    ("SHELL", "--shell"),
+    ("HEALTHCHECK", "--healthcheck"),
+    ("STOPSIGNAL", "--stop-signal"),
+];
+
+/// State shared between the native functions exposed to a script.
+#[derive(Clone)]
+struct State {
+    context : Rc<BuildContext>,
+    ctr     : Rc<RefCell<Option<String>>>,
+    keys    : Rc<Vec<u64>>,
+    resume  : Option<usize>,
+    /// The number of CHECKPOINT directives evaluated so far.
+    count   : Rc<Cell<usize>>,
+    /// Set while skipping directives that precede the checkpoint being resumed from.
+    skip    : Rc<Cell<bool>>,
+}
+
+impl State {
+    /// Fetch the working container, failing if FROM has not been called yet.
+    fn ctr(&self) -> NativeResult<String> {
+        self
+            .ctr
+            .borrow()
+            .clone()
+            .ok_or_else(|| "FROM must be called before any other directive".into())
+    }
+
+    fn from(&self, args: Vec<String>) -> NativeResult<()> {
+        if self.skip.get() {
+            return Ok(())
+        }
+
+        let ctr = native(
+            crate::create_container(&args, &self.context)
+        )?;
+
+        self.ctr.replace(Some(ctr));
+
+        Ok(())
+    }
+
+    fn directive(&self, operation: &str, args: Vec<String>) -> NativeResult<()> {
+        if self.skip.get() {
+            return Ok(())
+        }
+
+        native(
+            crate::evaluate_directive(&self.ctr()?, operation, &args)
+        )
+    }
+
+    fn config(&self, flag: &str, value: &str) -> NativeResult<()> {
+        if self.skip.get() {
+            return Ok(())
+        }
+
+        let result = Command::new("buildah")
+            .arg("config")
+            .arg(flag)
+            .arg(value)
+            .arg(self.ctr()?)
+            .spawn_ok()
+            .context("Fault when configuring working container");
+
+        native(result)
+    }
+
+    fn checkpoint(&self) -> NativeResult<()> {
+        let index = self.count.get() + 1;
+
+        self.count.set(index);
+
+        let Some(key) = self.keys.get(index - 1).copied() else {
+            if index == self.keys.len() + 1 {
+                warn!(
+                    "{} called more checkpoints than it has unindented CHECKPOINT lines - ignoring the rest",
+                    self.context.name
+                );
+            }
+
+            return Ok(())
+        };
+
+        let image = checkpoint_image(&self.context.name, key);
+
+        if !self.skip.get() {
+            return native(
+                crate::commit_checkpoint(&self.ctr()?, &image)
+            )
+        }
+
+        if self.resume == Some(index) {
+            let ctr = native(
+                crate::resume_container(&image, &self.context)
+            )?;
+
+            self.ctr.replace(Some(ctr));
+            self.skip.set(false);
+        }
+
+        Ok(())
+    }
+}
+
+/// Evaluate a Rhai-based definition in-process.
+pub fn evaluate(def: &Definition, script: &str, checkpoints: &Checkpoints) -> Result<()> {
+    let state = State {
+        context : Rc::new(def.context()),
+        ctr     : Rc::default(),
+        keys    : Rc::new(checkpoints.keys.clone()),
+        resume  : checkpoints.resume,
+        count   : Rc::default(),
+        skip    : Rc::new(Cell::new(checkpoints.resume.is_some())),
+    };
+
+    let mut engine = Engine::new();
+
+    register_directives(&mut engine, &state);
+
+    // Hermetic definitions only see the variables they allow.
+    let environment = Rc::new(def.environment());
+
+    let vars = environment.clone();
+    engine.register_fn("env", move |key: &str| -> NativeResult<String> {
+        lookup(&vars, key)
+            .ok_or_else(|| format!("environment variable {key} is not set (or not allowed)").into())
+    });
+
+    let vars = environment.clone();
+    engine.register_fn("env", move |key: &str, default: &str| -> String {
+        lookup(&vars, key)
+            .unwrap_or_else(|| default.to_owned())
+    });
+
+    let mut build = Map::new();
+
+    build.insert("name".into(), def.name().into());
+    build.insert("path".into(), def.path.to_string_lossy().to_string().into());
+    build.insert("dir".into(), def.directory().to_string_lossy().to_string().into());
+    build.insert("hash".into(), format!("{:x}", def.hash).into());
+    build.insert("tree".into(), format!("{:x}", def.tree).into());
+
+    let mut scope = Scope::new();
+
+    scope.push_constant("BUILD", build);
+
+    // Mirror the harnesses, which run definitions in their own directory.
+    let previous = std::env::current_dir()
+        .context("Fault when fetching working directory")?;
+
+    set_directory(&def.directory())?;
+
+    // Commands spawned by directives get the same environment as the shell harness would.
+    let result = crate::with_environment(def.environment(), || {
+        engine
+            .run_with_scope(&mut scope, script)
+            .map_err(|e| eyre!("{e}"))
+    });
+
+    set_directory(&previous)?;
+
+    result
+}
+
+/// Look up a variable in the environment of a hermetic definition, or the process
+/// environment if it isn't hermetic.
+fn lookup(vars: &Option<Vec<(OsString, OsString)>>, key: &str) -> Option<String> {
+    match vars {
+        Some(vars) => vars
+            .iter()
+            .find(|(k, _)| k == key)
+            .and_then(|(_, v)| v.to_str())
+            .map(str::to_owned),
+        None => std::env::var(key).ok()
+    }
+}
+
+fn register_directives(engine: &mut Engine, state: &State) {
+    let s = state.clone();
+    engine.register_fn("FROM", move |image: &str| s.from(vec![image.to_owned()]));
+
+    let s = state.clone();
+    engine.register_fn("FROM", move |args: Array| s.from(strings(args)));
+
+    // As in a Containerfile, the string form of RUN is evaluated by a shell
+    // while the array form is executed directly.
+    let s = state.clone();
+    engine.register_fn("RUN", move |cmd: &str| {
+        s.directive("run", vec!["sh".into(), "-c".into(), cmd.to_owned()])
+    });
+
+    let s = state.clone();
+    engine.register_fn("RUN", move |args: Array| s.directive("run", strings(args)));
+
+    for name in ["ADD", "COPY"] {
+        let s = state.clone();
+        engine.register_fn(name, move |src: &str, dst: &str| {
+            s.directive("add", vec![src.to_owned(), dst.to_owned()])
+        });
+
+        let s = state.clone();
+        engine.register_fn(name, move |args: Array| s.directive("add", strings(args)));
+    }
+
+    let s = state.clone();
+    engine.register_fn("ENV", move |pair: &str| s.config("--env", pair));
+
+    let s = state.clone();
+    engine.register_fn("ENV", move |key: &str, value: &str| {
+        s.config("--env", &format!("{key}={value}"))
+    });
+
+    let s = state.clone();
+    engine.register_fn("CFG", move |option: &str, value: &str| {
+        s.directive(option, vec![value.to_owned()])
+    });
+
+    let s = state.clone();
+    engine.register_fn("CFG", move |option: &str, values: Array| {
+        s.directive(option, strings(values))
+    });
+
+    let s = state.clone();
+    engine.register_fn("PRESET", move |name: &str| {
+        s.directive("preset", vec![name.to_owned()])
+    });
+
+    let s = state.clone();
+    engine.register_fn("PRESET", move |name: &str, args: Array| {
+        let mut args = strings(args);
+        args.insert(0, name.to_owned());
+        s.directive("preset", args)
+    });
+
+    let s = state.clone();
+    engine.register_fn("COMMIT", move |image: &str| {
+        s.directive("commit", vec![image.to_owned()])
+    });
+
+    let s = state.clone();
+    engine.register_fn("COMMIT", move |args: Array| s.directive("commit", strings(args)));
+
+    let s = state.clone();
+    engine.register_fn("CHECKPOINT", move || s.checkpoint());
+
+    for (name, flag) in CONFIG_DIRECTIVES {
+        let s = state.clone();
+        engine.register_fn(name, move |value: &str| s.config(flag, value));
+    }
+
+    // Exec form, which buildah accepts as a JSON array.
+    for (name, flag) in [("CMD", "--cmd"), ("ENTRYPOINT", "--entrypoint")] {
+        let s = state.clone();
+        engine.register_fn(name, move |args: Array| {
+            let json = serde_json::to_string(&strings(args))
+                .map_err(|e| e.to_string())?;
+
+            s.config(flag, &json)
+        });
+    }
+}
+
+/// Convert a script array into a list of arguments.
+fn strings(array: Array) -> Vec<String> {
+    array
+        .into_iter()
+        .map(|d: Dynamic| d.to_string())
+        .collect()
+}
+
+/// Convert a Box error into a script error, preserving its context chain.
+fn native<T>(result: Result<T>) -> NativeResult<T> {
+    result.map_err(|e| format!("{e:#}").into())
+}
+
+fn set_directory(path: &Path) -> Result<()> {
+    std::env::set_current_dir(path)
+        .context(
+            format!(
+                "Fault when changing working directory to {}",
+                path.to_string_lossy()
+            )
+        )
+}
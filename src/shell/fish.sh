@@ -14,21 +14,23 @@ end
 
 trap bx
 
+set -g __BOX_BUILD_CHECKPOINT 0
+
+if set -q __BOX_BUILD_RESUME
+    set -gx __BOX_BUILD_SKIP 1
+end
+
 function buildah
+    if set -q __BOX_BUILD_SKIP
+        return 0
+    end
+
     if [ $argv[1] = 'from' ]
-        set -l ctr (command buildah $argv)
+        set -l ctr (bx $argv)
 
         if [ $status -ne 0 ]
             exit $status
         end
-        
-        buildah config \
-            -a manager=box \
-            -a box.path=$__BOX_BUILD_PATH \
-            -a box.hash=$__BOX_BUILD_HASH \
-            -a box.tree=$__BOX_BUILD_TREE \
-            -a box.name=$__BOX_BUILD_NAME \
-            $ctr
 
         set -gx __BOX_BUILD_CTR $ctr
     else
@@ -112,4 +114,22 @@ function PRESET
     bx config preset $argv
 end
 
+function CHECKPOINT
+    set -g __BOX_BUILD_CHECKPOINT (math $__BOX_BUILD_CHECKPOINT + 1)
+
+    if not set -q __BOX_BUILD_SKIP
+        bx config checkpoint $__BOX_BUILD_CHECKPOINT
+    else if [ $__BOX_BUILD_CHECKPOINT = $__BOX_BUILD_RESUME ]
+        set -e __BOX_BUILD_SKIP
+
+        set -l ctr (bx config resume)
+
+        if [ $status -ne 0 ]
+            exit $status
+        end
+
+        set -gx __BOX_BUILD_CTR $ctr
+    end
+end
+
 cd $__BOX_BUILD_DIR
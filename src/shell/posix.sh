@@ -1,16 +1,18 @@
 set -eu
 
+__BOX_BUILD_CHECKPOINT=0
+
+if [ -n "${__BOX_BUILD_RESUME:-}" ]; then
+    export __BOX_BUILD_SKIP=1
+fi
+
 buildah() {
+    if [ -n "${__BOX_BUILD_SKIP:-}" ]; then
+        return 0
+    fi
+
     if [ "$1" = 'from' ]; then
-        ctr=$(command buildah "$@")
-        
-        buildah config \
-            -a manager=box \
-            -a box.path=$__BOX_BUILD_PATH \
-            -a box.hash=$__BOX_BUILD_HASH \
-            -a box.tree=$__BOX_BUILD_TREE \
-            -a box.name=$__BOX_BUILD_NAME \
-            "$ctr"
+        ctr=$(bx "$@")
 
         export __BOX_BUILD_CTR="$ctr"
     else
@@ -90,4 +92,18 @@ PRESET() {
     bx config preset $@
 }
 
+CHECKPOINT() {
+    __BOX_BUILD_CHECKPOINT=$((__BOX_BUILD_CHECKPOINT + 1))
+
+    if [ -z "${__BOX_BUILD_SKIP:-}" ]; then
+        bx config checkpoint "$__BOX_BUILD_CHECKPOINT"
+    elif [ "$__BOX_BUILD_CHECKPOINT" = "$__BOX_BUILD_RESUME" ]; then
+        unset __BOX_BUILD_SKIP
+
+        ctr=$(bx config resume)
+
+        export __BOX_BUILD_CTR="$ctr"
+    fi
+}
+
 cd $__BOX_BUILD_DIR
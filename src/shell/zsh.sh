@@ -0,0 +1,111 @@
+setopt ERR_EXIT NO_UNSET PIPE_FAIL
+
+__BOX_BUILD_CHECKPOINT=0
+
+if [[ -n "${__BOX_BUILD_RESUME:-}" ]]; then
+    export __BOX_BUILD_SKIP=1
+fi
+
+buildah() {
+    if [[ -n "${__BOX_BUILD_SKIP:-}" ]]; then
+        return 0
+    fi
+
+    if [[ "$1" == 'from' ]]; then
+        local ctr
+        ctr=$(bx "$@")
+
+        export __BOX_BUILD_CTR="$ctr"
+    else
+        command buildah "$@"
+    fi
+}
+
+FROM() {
+    buildah from "$@"
+}
+
+COMMIT() {
+    bx config commit "$@"
+}
+
+RUN() {
+    bx config run "$@"
+}
+
+ADD() {
+    bx config add "$@"
+}
+
+COPY() {
+    ADD "$@"
+}
+
+CMD() {
+    buildah config --cmd "$*" "$__BOX_BUILD_CTR"
+}
+
+LABEL() {
+    buildah config --label "$*" "$__BOX_BUILD_CTR"
+}
+
+EXPOSE() {
+    buildah config --port "$*" "$__BOX_BUILD_CTR"
+}
+
+ENV() {
+    buildah config --env "$*" "$__BOX_BUILD_CTR"
+}
+
+ENTRYPOINT() {
+    buildah config --entrypoint "$*" "$__BOX_BUILD_CTR"
+}
+
+VOLUME() {
+    buildah config --volume "$*" "$__BOX_BUILD_CTR"
+}
+
+USER() {
+    buildah config --user "$*" "$__BOX_BUILD_CTR"
+}
+
+WORKDIR() {
+    buildah config --workingdir "$*" "$__BOX_BUILD_CTR"
+}
+
+SHELL() {
+    buildah config --shell "$*" "$__BOX_BUILD_CTR"
+}
+
+HEALTHCHECK() {
+    buildah config --healthcheck "$*" "$__BOX_BUILD_CTR"
+}
+
+STOPSIGNAL() {
+    buildah config --stop-signal "$*" "$__BOX_BUILD_CTR"
+}
+
+CFG() {
+    bx config "$@"
+}
+
+PRESET() {
+    bx config preset "$@"
+}
+
+CHECKPOINT() {
+    __BOX_BUILD_CHECKPOINT=$((__BOX_BUILD_CHECKPOINT + 1))
+
+    if [[ -z "${__BOX_BUILD_SKIP:-}" ]]; then
+        bx config checkpoint "$__BOX_BUILD_CHECKPOINT"
+    elif [[ "$__BOX_BUILD_CHECKPOINT" == "$__BOX_BUILD_RESUME" ]]; then
+        unset __BOX_BUILD_SKIP
+
+        local ctr
+        ctr=$(bx config resume)
+
+        export __BOX_BUILD_CTR="$ctr"
+    fi
+}
+
+cd "$__BOX_BUILD_DIR"
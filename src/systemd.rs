@@ -0,0 +1,208 @@
+use std::fmt::Write;
+use std::path::PathBuf;
+
+use crate::prelude::*;
+use crate::podman::*;
+
+/// Render a managed image as a Podman Quadlet unit and install it
+/// (or print it to standard output, if `stdout` is set.)
+///
+/// `replace` controls whether or not an existing unit with the same name
+/// should be overwritten.
+pub fn generate_unit(id: &str, stdout: bool, replace: bool) -> Result<()> {
+    use colored::Colorize;
+
+    let image = Image::from_id(id)?;
+
+    let Some("box") = image.annotation("manager") else {
+        let err = eyre!("Image {id} is not managed by Box")
+            .suggestion("Box can only generate units for images built from definitions.");
+
+        return Err(err)
+    };
+
+    let name = image.annotation("box.name")
+        .expect("Name annotation should be set");
+
+    let unit = render_quadlet(&image)?;
+
+    if stdout {
+        print!("{unit}");
+        return Ok(())
+    }
+
+    let path = unit_directory()?
+        .join(
+            format!("{name}.container")
+        );
+
+    if path.exists() && !replace {
+        let err = eyre!(
+            "Unit {} already exists",
+            path.to_string_lossy()
+        )
+        .suggestion("Pass -r/--replace to overwrite it.");
+
+        return Err(err)
+    }
+
+    std::fs::write(&path, unit)
+        .context("Fault when writing unit to file")?;
+
+    eprintln!(
+        "{} {}",
+        "Wrote unit".bold().bright_white(),
+        path.to_string_lossy().green().bold()
+    );
+
+    eprintln!(
+        "Run {} and {} to start it.",
+        "systemctl --user daemon-reload".yellow(),
+        format!("systemctl --user start {name}").yellow()
+    );
+
+    Ok(())
+}
+
+/// Render the runtime configuration of a managed image as a Quadlet `.container` unit.
+///
+/// Options without a dedicated Quadlet key are passed through via `PodmanArgs`.
+pub fn render_quadlet(image: &Image) -> Result<String> {
+    let name = image.annotation("box.name")
+        .expect("Name annotation should be set");
+
+    let hash = image.annotation("box.hash")
+        .expect("Hash annotation should be set");
+
+    let mut container = String::new();
+    let mut service   = String::new();
+
+    // Writing to a String is infallible, so the results are discarded throughout.
+    let line = |buf: &mut String, key: &str, value: &str| {
+        let _ = writeln!(buf, "{key}={}", escape(value));
+    };
+
+    line(&mut container, "Image", name);
+    line(&mut container, "ContainerName", name);
+    line(&mut container, "HostName", name);
+    line(&mut container, "Annotation", "manager=box");
+    line(&mut container, "Annotation", &format!("box.name={name}"));
+    line(&mut container, "Annotation", &format!("box.hash={hash}"));
+
+    for (a, v) in image.runtime_config() {
+        match a {
+            "mount"    => line(&mut container, "Mount", v),
+            "device"   => line(&mut container, "AddDevice", v),
+            "cap-add"  => line(&mut container, "AddCapability", v),
+            "cap-drop" => line(&mut container, "DropCapability", v),
+            "ulimit"   => line(&mut container, "Ulimit", v),
+            "userns"   => line(&mut container, "UserNS", v),
+            "secret"   => line(&mut container, "Secret", v),
+            "args"     => line(&mut container, "PodmanArgs", &quote(v)),
+            "restart"  => {
+                // Quadlet containers are supervised by systemd, so Podman's
+                // restart policy is translated into the service equivalent.
+                let policy = match v.split(':').next() {
+                    Some("no")             => "no",
+                    Some("always")         => "always",
+                    Some("unless-stopped") => "always",
+                    Some("on-failure")     => "on-failure",
+                    _ => bail!("Unrecognized restart policy {v}")
+                };
+
+                if v.contains(':') {
+                    warn!("Restart policy {v} has a retry limit, which systemd units do not support - ignoring it");
+                }
+
+                line(&mut service, "Restart", policy)
+            },
+            _ => line(
+                &mut container,
+                "PodmanArgs",
+                &quote(&format!("--{a}={v}"))
+            )
+        }
+    }
+
+    let mut out = String::new();
+
+    let _ = writeln!(out, "# Generated by Box; changes will be lost if the unit is regenerated.");
+    let _ = writeln!(out, "[Unit]");
+    let _ = writeln!(out, "Description=Box container {name}");
+    let _ = writeln!(out);
+    let _ = writeln!(out, "[Container]");
+    let _ = write!(out, "{container}");
+    let _ = writeln!(out);
+    let _ = writeln!(out, "[Service]");
+    let _ = write!(out, "{service}");
+    let _ = writeln!(out);
+    let _ = writeln!(out, "[Install]");
+    let _ = writeln!(out, "WantedBy=default.target");
+
+    Ok(out)
+}
+
+/// Escape systemd specifiers (`%`) in a unit value.
+fn escape(value: &str) -> String {
+    value.replace('%', "%%")
+}
+
+/// Quote a single argument for a whitespace-separated unit value, if needed.
+fn quote(arg: &str) -> String {
+    if arg.is_empty() || arg.contains(char::is_whitespace) || arg.contains(['"', '\'', '\\']) {
+        let inner = arg
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"");
+
+        format!("\"{inner}\"")
+    }
+    else {
+        arg.to_owned()
+    }
+}
+
+/// Determines the directory to install Quadlet units into.
+///
+/// Existence checks these options, in this order:
+/// - `$XDG_CONFIG_HOME/containers/systemd`
+/// - `$HOME/.config/containers/systemd`
+pub fn unit_directory() -> Result<PathBuf> {
+    let options = || {
+        if let Ok(xdg_config) = std::env::var("XDG_CONFIG_HOME") {
+            return Some(
+                PathBuf::from(xdg_config)
+                    .join("containers")
+                    .join("systemd")
+            );
+        }
+
+        if let Ok(home) = std::env::var("HOME") {
+            return Some(
+                PathBuf::from(home)
+                    .join(".config")
+                    .join("containers")
+                    .join("systemd")
+            );
+        }
+
+        None
+    };
+
+    match options() {
+        Some(dir) => {
+            if !dir.exists() {
+                std::fs::create_dir_all(&dir)
+                    .context("Failed to create unit directory")?;
+            }
+
+            Ok(dir)
+        },
+        None => {
+            let err = eyre!("Could not find a valid directory for systemd units")
+                .note("Podman looks for Quadlet units in your user configuration directory.")
+                .suggestion("You likely have something wrong with your environment; Box tries:\n\t* $XDG_CONFIG_HOME/containers/systemd\n\t* $HOME/.config/containers/systemd\n... in that order.");
+
+            Err(err)
+        }
+    }
+}
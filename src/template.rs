@@ -0,0 +1,339 @@
+use std::path::PathBuf;
+
+use crate::prelude::*;
+use crate::build::*;
+
+/// A common base for development containers, used by built-in templates and the wizard.
+struct Base {
+    /// The name of the corresponding built-in template.
+    template    : &'static str,
+    /// A human-readable name for the base.
+    label       : &'static str,
+    /// The image to build from.
+    image       : &'static str,
+    /// Commands run to set up a basic development environment.
+    setup       : &'static [&'static str],
+}
+
+const BASES: [Base; 3] = [
+    Base {
+        template : "fedora-toolbox",
+        label    : "Fedora Toolbox",
+        image    : "registry.fedoraproject.org/fedora-toolbox:latest",
+        setup    : &["dnf install -y @development-tools"],
+    },
+    Base {
+        template : "debian",
+        label    : "Debian",
+        image    : "docker.io/library/debian:stable",
+        setup    : &["apt-get update", "apt-get install -y build-essential git sudo"],
+    },
+    Base {
+        template : "arch",
+        label    : "Arch Linux",
+        image    : "docker.io/library/archlinux:latest",
+        setup    : &["pacman -Syu --noconfirm base-devel git sudo"],
+    },
+];
+
+/// Presets offered by the wizard, along with a short description of each.
+const PRESETS: [(&str, &str); 4] = [
+    ("cp-user", "Copy your user into the container"),
+    ("bind-fix", "Fix permissions of rootless bind mounts"),
+    ("ssh-agent", "Forward your SSH agent"),
+    ("devices", "Mount /dev (implies --privileged)"),
+];
+
+/// Everything needed to generate a definition.
+struct Blueprint {
+    shell   : Shell,
+    image   : String,
+    setup   : Vec<String>,
+    presets : Vec<&'static str>,
+    /// Bind mounts, as (host, container) path pairs.
+    mounts  : Vec<(String, String)>,
+}
+
+impl Blueprint {
+    /// Create a blueprint for the provided base with sensible defaults.
+    fn from_base(base: &Base) -> Self {
+        Self {
+            shell   : Shell::Bash,
+            image   : base.image.to_owned(),
+            setup   : base.setup.iter().map(|s| s.to_string()).collect(),
+            presets : vec!["cp-user", "bind-fix"],
+            mounts  : vec![],
+        }
+    }
+
+    /// Render the blueprint as a definition named `name`.
+    fn render(&self, name: &str) -> String {
+        // The directives used here are identical across harnesses, so only the shebang varies.
+        let bang = match self.shell {
+            Shell::Posix => "#!/bin/sh".to_owned(),
+            shell => format!("#!/usr/bin/env {}", shell.name()),
+        };
+
+        let mut out = vec![
+            bang,
+            String::new(),
+            format!("FROM --pull=newer {}", self.image),
+            String::new(),
+        ];
+
+        for command in &self.setup {
+            out.push(format!("RUN {command}"));
+        }
+
+        if !self.setup.is_empty() {
+            out.push(String::new());
+        }
+
+        for preset in &self.presets {
+            out.push(format!("PRESET {preset}"));
+        }
+
+        if !self.presets.is_empty() {
+            out.push(String::new());
+        }
+
+        for (host, container) in &self.mounts {
+            out.push(format!("CFG mount type=bind,src={host},dst={container}"));
+        }
+
+        if !self.mounts.is_empty() {
+            out.push(String::new());
+        }
+
+        if self.presets.contains(&"cp-user") {
+            out.push("USER    $USER".to_owned());
+            out.push("WORKDIR /home/$USER".to_owned());
+            out.push(String::new());
+        }
+
+        out.extend([
+            "# Keep the container alive, so there's something to 'bx enter' into.".to_owned(),
+            "CMD     \"sleep inf\"".to_owned(),
+            "CFG args --init".to_owned(),
+            String::new(),
+            format!("COMMIT {name}"),
+        ]);
+
+        out.join("\n") + "\n"
+    }
+}
+
+/// The initial text of a new definition.
+pub struct Template {
+    pub text : String,
+    /// The extension the definition should be created with.
+    pub ext  : &'static str,
+}
+
+/// Determines the directory user templates are stored in.
+pub fn template_directory() -> Result<PathBuf> {
+    Ok(
+        definition_directory()?.join("templates")
+    )
+}
+
+/// Enumerate all available templates as (name, description) pairs, user templates first.
+pub fn available() -> Result<Vec<(String, String)>> {
+    let mut out = vec![];
+
+    if let Ok(entries) = std::fs::read_dir(template_directory()?) {
+        for entry in entries.flatten() {
+            if let Some(name) = definition_name(&entry.path()) {
+                out.push((
+                    name.to_owned(),
+                    "User template".to_owned()
+                ))
+            }
+        }
+    }
+
+    out.sort();
+
+    for base in &BASES {
+        if out.iter().any(|(n, _)| n == base.template) {
+            continue
+        }
+
+        out.push((
+            base.template.to_owned(),
+            format!("{} development container", base.label)
+        ));
+    }
+
+    Ok(out)
+}
+
+/// Find a template by name, rendering it for a definition named `name`.
+///
+/// User templates take precedence over built-in ones, and can refer to the
+/// name of the new definition as `{{name}}`.
+pub fn find(template: &str, name: &str) -> Result<Template> {
+    let dir = template_directory()?;
+
+    for ext in EXTENSIONS {
+        let path = dir.join(
+            format!("{template}{ext}")
+        );
+
+        if path.exists() {
+            let text = std::fs::read_to_string(&path)
+                .context("Fault when reading in template")?
+                .replace("{{name}}", name);
+
+            return Ok(Template { text, ext })
+        }
+    }
+
+    if let Some(base) = BASES.iter().find(|b| b.template == template) {
+        return Ok(Template {
+            text : Blueprint::from_base(base).render(name),
+            ext  : ".box",
+        })
+    }
+
+    let names: Vec<_> = available()?
+        .into_iter()
+        .map(|(n, _)| n)
+        .collect();
+
+    let err = eyre!("Template {template} does not exist")
+        .note(
+            format!("Available templates are: {}", names.join(", "))
+        )
+        .suggestion(
+            format!(
+                "Custom templates can be added to {}",
+                dir.to_string_lossy()
+            )
+        );
+
+    Err(err)
+}
+
+/// Interactively ask for the details of a new definition, then generate it.
+pub fn wizard(name: &str) -> Result<Template> {
+    use dialoguer::{Input, MultiSelect, Select};
+
+    let mut labels: Vec<_> = BASES
+        .iter()
+        .map(|b| format!("{} ({})", b.label, b.image))
+        .collect();
+
+    labels.push("Other".to_owned());
+
+    let choice = Select::new()
+        .with_prompt("Base image")
+        .items(&labels)
+        .default(0)
+        .interact()
+        .context("Fault when asking for base image")?;
+
+    let mut blueprint = match BASES.get(choice) {
+        Some(base) => Blueprint::from_base(base),
+        None => {
+            let image: String = Input::new()
+                .with_prompt("Image")
+                .interact_text()
+                .context("Fault when asking for base image")?;
+
+            Blueprint {
+                image,
+                setup: vec![],
+                ..Blueprint::from_base(&BASES[0])
+            }
+        }
+    };
+
+    let shells = [Shell::Bash, Shell::Zsh, Shell::Fish, Shell::Posix];
+
+    let labels: Vec<_> = shells
+        .iter()
+        .map(|s| s.description())
+        .collect();
+
+    let choice = Select::new()
+        .with_prompt("Shell")
+        .items(&labels)
+        .default(0)
+        .interact()
+        .context("Fault when asking for shell")?;
+
+    blueprint.shell = shells[choice];
+
+    let labels: Vec<_> = PRESETS
+        .iter()
+        .map(|(p, d)| format!("{p} - {d}"))
+        .collect();
+
+    let defaults: Vec<_> = PRESETS
+        .iter()
+        .map(|(p, _)| blueprint.presets.contains(p))
+        .collect();
+
+    let chosen = MultiSelect::new()
+        .with_prompt("Presets (space to toggle)")
+        .items(&labels)
+        .defaults(&defaults)
+        .interact()
+        .context("Fault when asking for presets")?;
+
+    blueprint.presets = chosen
+        .into_iter()
+        .map(|i| PRESETS[i].0)
+        .collect();
+
+    loop {
+        let mount: String = Input::new()
+            .with_prompt("Bind mount as HOST:CONTAINER (leave empty to finish)")
+            .allow_empty(true)
+            .interact_text()
+            .context("Fault when asking for mounts")?;
+
+        if mount.trim().is_empty() {
+            break
+        }
+
+        let Some((host, container)) = mount.trim().split_once(':') else {
+            eprintln!("Mounts must be formatted as HOST:CONTAINER - try again.");
+            continue
+        };
+
+        // Tildes aren't expanded inside of CFG arguments.
+        let host = match host.strip_prefix("~/") {
+            Some(rest) => format!("$HOME/{rest}"),
+            None => host.to_owned()
+        };
+
+        blueprint.mounts.push((
+            host,
+            container.to_owned()
+        ));
+    }
+
+    Ok(Template {
+        text : blueprint.render(name),
+        ext  : ".box",
+    })
+}
+
+/// List all available templates.
+pub fn list_templates() -> Result<()> {
+    use comfy_table::Table;
+    use comfy_table::presets::NOTHING;
+
+    let mut table = Table::new();
+
+    table
+        .load_preset(NOTHING)
+        .set_header(["Name", "Description"])
+        .add_rows(available()?.into_iter().map(|(n, d)| [n, d]));
+
+    println!("{table}");
+
+    Ok(())
+}